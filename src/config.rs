@@ -0,0 +1,178 @@
+use std::{collections::HashMap, fs, hash::Hash, io, path::Path, str::FromStr};
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::log;
+
+const CONFIG_EXTENSION: &str = "xentrace.toml";
+
+/// Per-stream configuration, read from the `<trace>.xentrace.toml` file
+/// next to the trace. Every setting is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// TSC frequency of the host, in the `XENTRACE_CPUHZ` format (eg. `"3.6G"`).
+    pub(crate) cpu_hz: Option<String>,
+    /// Offset (in nanoseconds) added to the timestamps of the stream.
+    pub(crate) time_offset: i64,
+    /// Names of the domains, keyed by domain ID.
+    #[serde(deserialize_with = "deserialize_ids")]
+    pub(crate) domains: HashMap<u16, String>,
+    /// Correction (in nanoseconds) added to the timestamps of each physical CPU,
+    /// keyed by CPU, for hosts whose TSC is not synchronized.
+    #[serde(deserialize_with = "deserialize_ids")]
+    pub(crate) cpu_skew: HashMap<u32, i64>,
+    /// TSC offset (in cycles) of the HVM guests from the host, keyed by domain ID.
+    #[serde(deserialize_with = "deserialize_ids")]
+    pub(crate) guest_tsc_offset: HashMap<u16, i64>,
+    /// Guest whose `trace.dat` stream the timestamps are aligned to.
+    pub(crate) align_guest: Option<u16>,
+    /// Saves the shifts of the guests next to the trace as `<trace>.guest-shift`.
+    pub(crate) guest_shift_file: bool,
+    /// How the domains are mapped to KernelShark PIDs.
+    pub(crate) pid_scheme: PidScheme,
+    /// Options of the record decoder.
+    pub(crate) decoder: DecoderConfig,
+}
+
+/// Mapping between the domains and the KernelShark PIDs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PidScheme {
+    /// One task per virtual CPU, with PID `domid << 15 | vcpu`.
+    #[default]
+    Vcpu,
+    /// One task per domain, with PID `domid`.
+    Domain,
+}
+
+/// Vendor of the host CPUs, whose virtualization extension numbers the VM exit reasons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CpuVendor {
+    /// VMX basic exit reasons.
+    Intel,
+    /// SVM exit codes.
+    Amd,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct DecoderConfig {
+    /// Shows the raw extra words instead of the decoded fields.
+    pub(crate) raw_info: bool,
+    /// Names the exit reasons after this vendor, rather than the detected one.
+    pub(crate) cpu_vendor: Option<CpuVendor>,
+}
+
+impl Config {
+    /// Loads the configuration of the given trace, if any.
+    ///
+    /// A missing file yields the default configuration,
+    /// a malformed one is reported and ignored.
+    pub(crate) fn load(trace_path: &Path) -> Self {
+        let mut config_path = trace_path.as_os_str().to_owned();
+        config_path.push(".");
+        config_path.push(CONFIG_EXTENSION);
+
+        let content = match fs::read_to_string(&config_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log!("Failed to read {config_path:?}: {e}");
+                return Self::default();
+            }
+        };
+
+        match Self::parse(&content) {
+            Ok(config) => {
+                log!("Configuration loaded from {config_path:?}");
+                config
+            }
+            Err(e) => {
+                log!("Failed to parse {config_path:?}: {e}");
+                Self::default()
+            }
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+}
+
+/// TOML keys are always strings, so the numeric IDs (of domains and CPUs) are parsed by hand.
+fn deserialize_ids<'de, D, K, V>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: FromStr + Eq + Hash,
+    V: Deserialize<'de>,
+{
+    HashMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(id, value)| match id.parse() {
+            Ok(id) => Ok((id, value)),
+            Err(_) => Err(D::Error::custom(format_args!("invalid ID \"{id}\""))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, CpuVendor, PidScheme};
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            r#"
+            cpu_hz = "3.6G"
+            time_offset = -1500
+            pid_scheme = "domain"
+            align_guest = 1
+            guest_shift_file = true
+
+            [domains]
+            1 = "webserver-01"
+            12 = "database"
+
+            [cpu_skew]
+            3 = -420
+
+            [guest_tsc_offset]
+            1 = -2500000000
+
+            [decoder]
+            raw_info = true
+            cpu_vendor = "amd"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.cpu_hz.as_deref(), Some("3.6G"));
+        assert_eq!(config.time_offset, -1500);
+        assert_eq!(
+            config.domains.get(&1).map(String::as_str),
+            Some("webserver-01")
+        );
+        assert_eq!(
+            config.domains.get(&12).map(String::as_str),
+            Some("database")
+        );
+        assert_eq!(config.cpu_skew.get(&3), Some(&-420));
+        assert_eq!(config.guest_tsc_offset.get(&1), Some(&-2_500_000_000));
+        assert_eq!(config.align_guest, Some(1));
+        assert!(config.guest_shift_file);
+        assert_eq!(config.pid_scheme, PidScheme::Domain);
+        assert!(config.decoder.raw_info);
+        assert_eq!(config.decoder.cpu_vendor, Some(CpuVendor::Amd));
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        assert!(Config::parse("cpu_mhz = 3600").is_err());
+        assert!(Config::parse("[domains]\nweb = \"webserver-01\"").is_err());
+        assert!(Config::parse("pid_scheme = \"thread\"").is_err());
+        assert!(Config::parse("[cpu_skew]\n1 = \"fast\"").is_err());
+        assert!(Config::parse("").is_ok());
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs,
+    path::Path,
+};
+
+use xentrace_parser::record::DomainKind;
+
+use super::{get_domain_task, timestamp::TscClock, DomainNames};
+use crate::{
+    config::Config,
+    ffi::xen::trace::TRC_HVM_RDTSC,
+    log,
+    store::{Record, TraceStore},
+};
+
+const GUEST_SHIFT_EXTENSION: &str = "guest-shift";
+
+/// TSC of a guest, as `scale * host_tsc + offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GuestClock {
+    pub(crate) offset: f64,
+    pub(crate) scale: f64,
+}
+
+/// Least-squares fit of the TSC of a guest over the host one.
+/// The samples are taken relative to the first one, to keep the precision.
+#[derive(Debug)]
+struct ClockFit {
+    first: (u64, u64),
+    count: usize,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_xy: f64,
+}
+
+impl ClockFit {
+    fn new(host_tsc: u64, guest_tsc: u64) -> Self {
+        Self {
+            first: (host_tsc, guest_tsc),
+            count: 0,
+            sum_x: 0.0,
+            sum_y: 0.0,
+            sum_xx: 0.0,
+            sum_xy: 0.0,
+        }
+    }
+
+    fn add(&mut self, host_tsc: u64, guest_tsc: u64) {
+        let x = (i128::from(host_tsc) - i128::from(self.first.0)) as f64;
+        let y = (i128::from(guest_tsc) - i128::from(self.first.1)) as f64;
+
+        self.count += 1;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_xy += x * y;
+    }
+
+    fn clock(&self) -> GuestClock {
+        let n = self.count as f64;
+        let variance = n * self.sum_xx - self.sum_x * self.sum_x;
+        let scale = match variance > 0.0 {
+            true => (n * self.sum_xy - self.sum_x * self.sum_y) / variance,
+            false => 1.0,
+        };
+
+        let intercept = (self.sum_y - scale * self.sum_x) / n;
+        GuestClock {
+            offset: self.first.1 as f64 + intercept - scale * self.first.0 as f64,
+            scale,
+        }
+    }
+}
+
+/// Clocks of the HVM guests, estimated from the TSC values
+/// returned by their `hwm:rdtsc` exits.
+#[derive(Debug, Default)]
+pub(crate) struct GuestClocks {
+    fits: BTreeMap<u16, ClockFit>,
+}
+
+impl GuestClocks {
+    /// Estimates the clocks from the whole trace, unless the guests never exited on `rdtsc`.
+    pub(crate) fn new(trace: &TraceStore) -> Self {
+        let mut guests = Self::default();
+        if trace.contains_code(TRC_HVM_RDTSC) {
+            trace.iter().for_each(|record| guests.add(&record));
+        }
+
+        guests
+    }
+
+    fn add(&mut self, record: &Record) {
+        let event = record.event();
+        if event.code() != TRC_HVM_RDTSC {
+            return;
+        }
+
+        let kind = record.domain().kind();
+        let [Some(low), Some(high), ..] = *event.extra() else {
+            return;
+        };
+
+        if let DomainKind::Guest(_) = kind {
+            let guest_tsc = u64::from(low) | u64::from(high) << 32;
+            self.fits
+                .entry(u16::from(kind))
+                .or_insert_with(|| ClockFit::new(event.tsc(), guest_tsc))
+                .add(event.tsc(), guest_tsc);
+        }
+    }
+
+    /// Returns the estimated clock of a guest and the number of samples it comes from.
+    pub(crate) fn get(&self, dom_id: u16) -> Option<(GuestClock, usize)> {
+        self.fits.get(&dom_id).map(|fit| (fit.clock(), fit.count))
+    }
+
+    /// Returns the time offset of the XenTrace stream, which lines up with the
+    /// `trace.dat` stream of the `align_guest` one if set. The shift aligning the
+    /// stream of each guest is printed, and saved next to the trace as
+    /// `<trace>.guest-shift` if `guest_shift_file` is set.
+    /// An offset of the configuration file takes precedence.
+    pub(crate) fn get_time_offset(
+        &self,
+        trace_path: &Path,
+        clock: &TscClock,
+        config: &Config,
+        names: &DomainNames,
+    ) -> i64 {
+        let dom_ids = self
+            .fits
+            .keys()
+            .chain(config.guest_tsc_offset.keys())
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        let guests = dom_ids
+            .into_iter()
+            .filter_map(|dom_id| {
+                let (guest, source) = match config.guest_tsc_offset.get(&dom_id) {
+                    Some(offset) => {
+                        let guest = GuestClock {
+                            offset: *offset as f64,
+                            scale: 1.0,
+                        };
+                        (guest, "configuration file".to_string())
+                    }
+                    None => {
+                        let (guest, samples) = self.get(dom_id)?;
+                        (guest, format!("{samples} hwm:rdtsc records"))
+                    }
+                };
+
+                let zero_ns = clock.get_zero_ns(guest.offset, guest.scale);
+                Some((dom_id, guest, source, zero_ns))
+            })
+            .collect::<Vec<_>>();
+
+        let time_offset = match config.align_guest {
+            Some(align_guest) => {
+                match guests.iter().find(|guest| guest.0 == align_guest) {
+                    Some((.., zero_ns)) => config.time_offset.saturating_sub(*zero_ns),
+                    None => {
+                        log!("No TSC is known for guest {align_guest}, the stream is not aligned to it");
+                        config.time_offset
+                    }
+                }
+            }
+            None => config.time_offset,
+        };
+
+        let mut shifts = String::new();
+        for (dom_id, guest, source, zero_ns) in guests {
+            let shift = zero_ns.saturating_add(time_offset);
+            let task = get_domain_task(dom_id, None, names);
+            log!(
+                "Guest {task}: TSC = {:.6} * host TSC + {:.0} (from {source}), shift its trace.dat stream by {shift} ns",
+                guest.scale,
+                guest.offset,
+            );
+            let _ = writeln!(shifts, "{dom_id} = {shift} # {task}");
+        }
+
+        if config.guest_shift_file && !shifts.is_empty() {
+            write_shifts(trace_path, &shifts);
+        }
+
+        time_offset
+    }
+}
+
+/// Writes the shifts of the guests, keyed by domain ID, as a TOML table.
+fn write_shifts(trace_path: &Path, shifts: &str) {
+    let mut shift_path = trace_path.as_os_str().to_owned();
+    shift_path.push(".");
+    shift_path.push(GUEST_SHIFT_EXTENSION);
+
+    let content = format!(
+        "# Shift (in nanoseconds) of the trace.dat stream of each guest, keyed by domain ID\n{shifts}"
+    );
+    match fs::write(&shift_path, content) {
+        Ok(()) => log!("Guest shifts saved to {shift_path:?}"),
+        Err(e) => log!("Failed to write {shift_path:?}: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::{GuestClock, GuestClocks};
+    use crate::{
+        config::Config,
+        decoder::{timestamp::TscClock, DomainNames},
+        store::TraceStore,
+        util::test::trace_bytes,
+    };
+
+    #[test]
+    fn guest_clock_fit() {
+        // Domain 1 is scheduled, then its TSC is read 3 times
+        let offset = 5_000_000_000_000u64;
+        let guest_tsc = |host_tsc: u64| {
+            let tsc = host_tsc + offset;
+            [tsc as u32, (tsc >> 32) as u32]
+        };
+
+        let trace = TraceStore::from_bytes(&trace_bytes(&[
+            (0x00021002, 100, &[0x0001_0000]),
+            (0x0008201A, 1_000, &guest_tsc(1_000)),
+            (0x0008201A, 2_000, &guest_tsc(2_000)),
+            (0x0008201A, 4_000, &guest_tsc(4_000)),
+        ]));
+
+        let guests = GuestClocks::new(&trace);
+
+        let (clock, samples) = guests.get(1).unwrap();
+        assert_eq!(samples, 3);
+        assert_eq!(
+            clock,
+            GuestClock {
+                offset: offset as f64,
+                scale: 1.0
+            }
+        );
+        assert_eq!(guests.get(0), None);
+    }
+
+    #[test]
+    fn guest_shift_file() {
+        let trace = TraceStore::from_bytes(&trace_bytes(&[(0x00021002, 0, &[0x0001_0000])]));
+        let clock = TscClock::new(&trace, 2_400_000_000.0);
+        let mut config = Config {
+            guest_tsc_offset: [(1, -2_400), (2, -4_800)].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let trace_path = env::temp_dir().join(format!("xentrace-{}.bin", std::process::id()));
+        let shift_path = trace_path.with_extension("bin.guest-shift");
+        let get_time_offset = |config: &Config| {
+            GuestClocks::new(&trace).get_time_offset(
+                &trace_path,
+                &clock,
+                config,
+                &DomainNames::default(),
+            )
+        };
+
+        // The file is only written on demand
+        assert_eq!(get_time_offset(&config), 0);
+        assert!(!shift_path.exists());
+
+        config.guest_shift_file = true;
+        assert_eq!(get_time_offset(&config), 0);
+        let content = fs::read_to_string(&shift_path).unwrap();
+        assert_eq!(content.lines().nth(1), Some("1 = 1000 # d1"));
+
+        // Aligned to the guest 1, the stream is shifted rather than its own
+        config.align_guest = Some(1);
+        assert_eq!(get_time_offset(&config), -1_000);
+        let content = fs::read_to_string(&shift_path).unwrap();
+        fs::remove_file(&shift_path).unwrap();
+        assert_eq!(
+            content.lines().skip(1).collect::<Vec<_>>(),
+            ["1 = 0 # d1", "2 = 1000 # d2"]
+        );
+    }
+}
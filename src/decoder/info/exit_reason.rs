@@ -0,0 +1,392 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use fxhash::FxBuildHasher;
+
+use crate::{
+    config::{Config, CpuVendor},
+    ffi::xen::trace::{TRC_HVM_VMEXIT, TRC_HVM_VMEXIT64},
+    log,
+    store::TraceStore,
+};
+
+/// Set in the VMX exit reason when the VM entry failed.
+const VMX_ENTRY_FAILURE: u32 = 0x80000000;
+/// The VMX basic exit reasons all stay below the first SVM exit code without an Intel equivalent.
+const SVM_ONLY_MIN: u32 = 0x60;
+/// Number of VM exits after which a trace without SVM-only exit codes is taken as VMX.
+const VENDOR_SCAN_EXITS: usize = 4096;
+
+// Basic exit reasons, from the Intel SDM (Vol. 3, Appendix C)
+const VMX_EXIT_REASONS: &[(u16, &str)] = &[
+    (0x00, "EXCEPTION_NMI"),
+    (0x01, "EXTERNAL_INTERRUPT"),
+    (0x02, "TRIPLE_FAULT"),
+    (0x03, "INIT"),
+    (0x04, "SIPI"),
+    (0x05, "IO_SMI"),
+    (0x06, "OTHER_SMI"),
+    (0x07, "PENDING_VIRT_INTR"),
+    (0x08, "PENDING_VIRT_NMI"),
+    (0x09, "TASK_SWITCH"),
+    (0x0A, "CPUID"),
+    (0x0B, "GETSEC"),
+    (0x0C, "HLT"),
+    (0x0D, "INVD"),
+    (0x0E, "INVLPG"),
+    (0x0F, "RDPMC"),
+    (0x10, "RDTSC"),
+    (0x11, "RSM"),
+    (0x12, "VMCALL"),
+    (0x13, "VMCLEAR"),
+    (0x14, "VMLAUNCH"),
+    (0x15, "VMPTRLD"),
+    (0x16, "VMPTRST"),
+    (0x17, "VMREAD"),
+    (0x18, "VMRESUME"),
+    (0x19, "VMWRITE"),
+    (0x1A, "VMXOFF"),
+    (0x1B, "VMXON"),
+    (0x1C, "CR_ACCESS"),
+    (0x1D, "DR_ACCESS"),
+    (0x1E, "IO_INSTRUCTION"),
+    (0x1F, "MSR_READ"),
+    (0x20, "MSR_WRITE"),
+    (0x21, "INVALID_GUEST_STATE"),
+    (0x22, "MSR_LOADING"),
+    (0x24, "MWAIT_INSTRUCTION"),
+    (0x25, "MONITOR_TRAP_FLAG"),
+    (0x27, "MONITOR_INSTRUCTION"),
+    (0x28, "PAUSE_INSTRUCTION"),
+    (0x29, "MCE_DURING_VMENTRY"),
+    (0x2B, "TPR_BELOW_THRESHOLD"),
+    (0x2C, "APIC_ACCESS"),
+    (0x2D, "EOI_INDUCED"),
+    (0x2E, "ACCESS_GDTR_OR_IDTR"),
+    (0x2F, "ACCESS_LDTR_OR_TR"),
+    (0x30, "EPT_VIOLATION"),
+    (0x31, "EPT_MISCONFIG"),
+    (0x32, "INVEPT"),
+    (0x33, "RDTSCP"),
+    (0x34, "VMX_PREEMPTION_TIMER_EXPIRED"),
+    (0x35, "INVVPID"),
+    (0x36, "WBINVD"),
+    (0x37, "XSETBV"),
+    (0x38, "APIC_WRITE"),
+    (0x39, "RDRAND"),
+    (0x3A, "INVPCID"),
+    (0x3B, "VMFUNC"),
+    (0x3C, "ENCLS"),
+    (0x3D, "RDSEED"),
+    (0x3E, "PML_FULL"),
+    (0x3F, "XSAVES"),
+    (0x40, "XRSTORS"),
+    (0x41, "PCONFIG"),
+    (0x42, "SPP"),
+    (0x43, "UMWAIT"),
+    (0x44, "TPAUSE"),
+    (0x45, "LOADIWKEY"),
+    (0x46, "ENCLV"),
+    (0x48, "ENQCMD_PASID_FAIL"),
+    (0x49, "ENQCMDS_PASID_FAIL"),
+    (0x4A, "BUS_LOCK"),
+    (0x4B, "NOTIFY"),
+];
+
+// Exit codes, from the AMD APM (Vol. 2, Appendix C)
+const SVM_EXIT_CODES: &[(u32, &str)] = &[
+    (0x000, "CR0_READ"),
+    (0x001, "CR1_READ"),
+    (0x002, "CR2_READ"),
+    (0x003, "CR3_READ"),
+    (0x004, "CR4_READ"),
+    (0x005, "CR5_READ"),
+    (0x006, "CR6_READ"),
+    (0x007, "CR7_READ"),
+    (0x008, "CR8_READ"),
+    (0x009, "CR9_READ"),
+    (0x00A, "CR10_READ"),
+    (0x00B, "CR11_READ"),
+    (0x00C, "CR12_READ"),
+    (0x00D, "CR13_READ"),
+    (0x00E, "CR14_READ"),
+    (0x00F, "CR15_READ"),
+    (0x010, "CR0_WRITE"),
+    (0x011, "CR1_WRITE"),
+    (0x012, "CR2_WRITE"),
+    (0x013, "CR3_WRITE"),
+    (0x014, "CR4_WRITE"),
+    (0x015, "CR5_WRITE"),
+    (0x016, "CR6_WRITE"),
+    (0x017, "CR7_WRITE"),
+    (0x018, "CR8_WRITE"),
+    (0x019, "CR9_WRITE"),
+    (0x01A, "CR10_WRITE"),
+    (0x01B, "CR11_WRITE"),
+    (0x01C, "CR12_WRITE"),
+    (0x01D, "CR13_WRITE"),
+    (0x01E, "CR14_WRITE"),
+    (0x01F, "CR15_WRITE"),
+    (0x020, "DR0_READ"),
+    (0x021, "DR1_READ"),
+    (0x022, "DR2_READ"),
+    (0x023, "DR3_READ"),
+    (0x024, "DR4_READ"),
+    (0x025, "DR5_READ"),
+    (0x026, "DR6_READ"),
+    (0x027, "DR7_READ"),
+    (0x028, "DR8_READ"),
+    (0x029, "DR9_READ"),
+    (0x02A, "DR10_READ"),
+    (0x02B, "DR11_READ"),
+    (0x02C, "DR12_READ"),
+    (0x02D, "DR13_READ"),
+    (0x02E, "DR14_READ"),
+    (0x02F, "DR15_READ"),
+    (0x030, "DR0_WRITE"),
+    (0x031, "DR1_WRITE"),
+    (0x032, "DR2_WRITE"),
+    (0x033, "DR3_WRITE"),
+    (0x034, "DR4_WRITE"),
+    (0x035, "DR5_WRITE"),
+    (0x036, "DR6_WRITE"),
+    (0x037, "DR7_WRITE"),
+    (0x038, "DR8_WRITE"),
+    (0x039, "DR9_WRITE"),
+    (0x03A, "DR10_WRITE"),
+    (0x03B, "DR11_WRITE"),
+    (0x03C, "DR12_WRITE"),
+    (0x03D, "DR13_WRITE"),
+    (0x03E, "DR14_WRITE"),
+    (0x03F, "DR15_WRITE"),
+    (0x040, "EXCEPTION_DE"),
+    (0x041, "EXCEPTION_DB"),
+    (0x042, "EXCEPTION_NMI"),
+    (0x043, "EXCEPTION_BP"),
+    (0x044, "EXCEPTION_OF"),
+    (0x045, "EXCEPTION_BR"),
+    (0x046, "EXCEPTION_UD"),
+    (0x047, "EXCEPTION_NM"),
+    (0x048, "EXCEPTION_DF"),
+    (0x049, "EXCEPTION_09"),
+    (0x04A, "EXCEPTION_TS"),
+    (0x04B, "EXCEPTION_NP"),
+    (0x04C, "EXCEPTION_SS"),
+    (0x04D, "EXCEPTION_GP"),
+    (0x04E, "EXCEPTION_PF"),
+    (0x04F, "EXCEPTION_0F"),
+    (0x050, "EXCEPTION_MF"),
+    (0x051, "EXCEPTION_AC"),
+    (0x052, "EXCEPTION_MC"),
+    (0x053, "EXCEPTION_XF"),
+    (0x054, "EXCEPTION_VE"),
+    (0x055, "EXCEPTION_CP"),
+    (0x056, "EXCEPTION_16"),
+    (0x057, "EXCEPTION_17"),
+    (0x058, "EXCEPTION_18"),
+    (0x059, "EXCEPTION_19"),
+    (0x05A, "EXCEPTION_1A"),
+    (0x05B, "EXCEPTION_1B"),
+    (0x05C, "EXCEPTION_HV"),
+    (0x05D, "EXCEPTION_VC"),
+    (0x05E, "EXCEPTION_SX"),
+    (0x05F, "EXCEPTION_1F"),
+    (0x060, "INTR"),
+    (0x061, "NMI"),
+    (0x062, "SMI"),
+    (0x063, "INIT"),
+    (0x064, "VINTR"),
+    (0x065, "CR0_SEL_WRITE"),
+    (0x066, "IDTR_READ"),
+    (0x067, "GDTR_READ"),
+    (0x068, "LDTR_READ"),
+    (0x069, "TR_READ"),
+    (0x06A, "IDTR_WRITE"),
+    (0x06B, "GDTR_WRITE"),
+    (0x06C, "LDTR_WRITE"),
+    (0x06D, "TR_WRITE"),
+    (0x06E, "RDTSC"),
+    (0x06F, "RDPMC"),
+    (0x070, "PUSHF"),
+    (0x071, "POPF"),
+    (0x072, "CPUID"),
+    (0x073, "RSM"),
+    (0x074, "IRET"),
+    (0x075, "SWINT"),
+    (0x076, "INVD"),
+    (0x077, "PAUSE"),
+    (0x078, "HLT"),
+    (0x079, "INVLPG"),
+    (0x07A, "INVLPGA"),
+    (0x07B, "IOIO"),
+    (0x07C, "MSR"),
+    (0x07D, "TASK_SWITCH"),
+    (0x07E, "FERR_FREEZE"),
+    (0x07F, "SHUTDOWN"),
+    (0x080, "VMRUN"),
+    (0x081, "VMMCALL"),
+    (0x082, "VMLOAD"),
+    (0x083, "VMSAVE"),
+    (0x084, "STGI"),
+    (0x085, "CLGI"),
+    (0x086, "SKINIT"),
+    (0x087, "RDTSCP"),
+    (0x088, "ICEBP"),
+    (0x089, "WBINVD"),
+    (0x08A, "MONITOR"),
+    (0x08B, "MWAIT"),
+    (0x08C, "MWAIT_CONDITIONAL"),
+    (0x08D, "XSETBV"),
+    (0x08E, "RDPRU"),
+    (0x08F, "EFER_WRITE_TRAP"),
+    (0x090, "CR0_WRITE_TRAP"),
+    (0x091, "CR1_WRITE_TRAP"),
+    (0x092, "CR2_WRITE_TRAP"),
+    (0x093, "CR3_WRITE_TRAP"),
+    (0x094, "CR4_WRITE_TRAP"),
+    (0x095, "CR5_WRITE_TRAP"),
+    (0x096, "CR6_WRITE_TRAP"),
+    (0x097, "CR7_WRITE_TRAP"),
+    (0x098, "CR8_WRITE_TRAP"),
+    (0x099, "CR9_WRITE_TRAP"),
+    (0x09A, "CR10_WRITE_TRAP"),
+    (0x09B, "CR11_WRITE_TRAP"),
+    (0x09C, "CR12_WRITE_TRAP"),
+    (0x09D, "CR13_WRITE_TRAP"),
+    (0x09E, "CR14_WRITE_TRAP"),
+    (0x09F, "CR15_WRITE_TRAP"),
+    (0x0A0, "INVLPGB"),
+    (0x0A1, "INVLPGB_ILLEGAL"),
+    (0x0A2, "INVPCID"),
+    (0x0A3, "MCOMMIT"),
+    (0x0A4, "TLBSYNC"),
+    (0x400, "NPF"),
+    (0x401, "AVIC_INCOMPLETE_IPI"),
+    (0x402, "AVIC_NOACCEL"),
+    (0x403, "VMGEXIT"),
+    (0xFFFFFFFF, "INVALID"),
+];
+
+lazy_static::lazy_static! {
+    static ref VMX_MAP: HashMap<u16, &'static str, FxBuildHasher> = VMX_EXIT_REASONS.iter().copied().collect();
+    static ref SVM_MAP: HashMap<u32, &'static str, FxBuildHasher> = SVM_EXIT_CODES.iter().copied().collect();
+}
+
+/// Writes the name of a VM exit reason, or its hex value if unknown.
+pub(crate) fn write_exit_reason(out: &mut String, reason: u32, vendor: Option<CpuVendor>) {
+    let name = match vendor {
+        Some(CpuVendor::Intel) => VMX_MAP.get(&(reason as u16)).copied(),
+        Some(CpuVendor::Amd) => SVM_MAP.get(&reason).copied(),
+        None => None,
+    };
+
+    match name {
+        Some(name) => {
+            out.push_str(name);
+            if vendor == Some(CpuVendor::Intel) && reason & VMX_ENTRY_FAILURE != 0 {
+                out.push_str("|ENTRY_FAILURE");
+            }
+        }
+        None => {
+            let _ = write!(out, "0x{reason:X}");
+        }
+    }
+}
+
+/// Tells Intel (VMX) from AMD (SVM) hosts by their exit reasons: only SVM
+/// has exit codes from 0x60 on (eg. `INTR`, met on any busy host), while the
+/// SVM codes of the missing control registers (CR1, CR5-7 and CR9-15) are
+/// common VMX reasons (eg. `EXTERNAL_INTERRUPT`, `HLT` or `IO_INSTRUCTION`).
+///
+/// The scan stops at the first exit telling them apart, or after
+/// `VENDOR_SCAN_EXITS` exits.
+fn get_trace_cpu_vendor(trace: &TraceStore) -> Option<CpuVendor> {
+    if !trace.contains_code(TRC_HVM_VMEXIT) && !trace.contains_code(TRC_HVM_VMEXIT64) {
+        return None;
+    }
+
+    let reasons = trace
+        .iter()
+        .filter(|record| {
+            matches!(
+                record.event().code().value(),
+                TRC_HVM_VMEXIT | TRC_HVM_VMEXIT64
+            )
+        })
+        .filter_map(|record| record.event().extra()[0])
+        .take(VENDOR_SCAN_EXITS);
+
+    let mut vendor = None;
+    for reason in reasons.map(|reason| reason & 0xFFFF) {
+        if reason >= SVM_ONLY_MIN {
+            return Some(CpuVendor::Amd);
+        }
+
+        if reason < 0x20 && matches!(reason & 0xF, 1 | 5..=7 | 9..=15) {
+            return Some(CpuVendor::Intel);
+        }
+
+        vendor = Some(CpuVendor::Intel);
+    }
+
+    vendor
+}
+
+/// Detects the vendor of the host CPUs, unless set by the configuration file.
+pub(crate) fn detect_cpu_vendor(trace: &TraceStore, config: &Config) -> Option<CpuVendor> {
+    let (vendor, source) = match config.decoder.cpu_vendor {
+        Some(vendor) => (vendor, "configuration file"),
+        None => (get_trace_cpu_vendor(trace)?, "hwm:vmexit records"),
+    };
+
+    log!("CPU vendor: {vendor:?} (from {source})");
+    Some(vendor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_trace_cpu_vendor, write_exit_reason};
+    use crate::{config::CpuVendor, store::TraceStore, util::test::trace_bytes};
+
+    fn exit_reason(reason: u32, vendor: Option<CpuVendor>) -> String {
+        let mut out = String::new();
+        write_exit_reason(&mut out, reason, vendor);
+        out
+    }
+
+    #[test]
+    fn exit_reason_names() {
+        assert_eq!(exit_reason(0x30, Some(CpuVendor::Intel)), "EPT_VIOLATION");
+        assert_eq!(
+            exit_reason(0x80000021, Some(CpuVendor::Intel)),
+            "INVALID_GUEST_STATE|ENTRY_FAILURE"
+        );
+        assert_eq!(exit_reason(0x400, Some(CpuVendor::Amd)), "NPF");
+        assert_eq!(exit_reason(0x4E, Some(CpuVendor::Amd)), "EXCEPTION_PF");
+        assert_eq!(exit_reason(0x30, Some(CpuVendor::Amd)), "DR0_WRITE");
+        assert_eq!(exit_reason(0x30, None), "0x30");
+        assert_eq!(exit_reason(0x1234, Some(CpuVendor::Amd)), "0x1234");
+    }
+
+    #[test]
+    fn cpu_vendor_detection() {
+        let vmx = TraceStore::from_bytes(&trace_bytes(&[
+            (0x00081002, 10, &[0x30, 0x1000]),
+            (0x00081102, 20, &[0x2C, 0x1000, 0]),
+        ]));
+        // HLT tells VMX apart, the later codes are not looked at
+        let vmx_first = TraceStore::from_bytes(&trace_bytes(&[
+            (0x00081002, 10, &[0x0C, 0x1000]),
+            (0x00081002, 20, &[0x60, 0x1000]),
+        ]));
+        let svm = TraceStore::from_bytes(&trace_bytes(&[
+            (0x00081002, 10, &[0x4E, 0x1000]),
+            (0x00081002, 20, &[0x60, 0x1000]),
+        ]));
+        let none = TraceStore::from_bytes(&trace_bytes(&[(0x00081001, 10, &[])]));
+
+        assert_eq!(get_trace_cpu_vendor(&vmx), Some(CpuVendor::Intel));
+        assert_eq!(get_trace_cpu_vendor(&vmx_first), Some(CpuVendor::Intel));
+        assert_eq!(get_trace_cpu_vendor(&svm), Some(CpuVendor::Amd));
+        assert_eq!(get_trace_cpu_vendor(&none), None);
+    }
+}
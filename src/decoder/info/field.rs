@@ -0,0 +1,180 @@
+use std::fmt::Write as _;
+
+use xentrace_parser::record::{EventCode, EVENT_EXTRA_CAPACITY};
+
+use super::{
+    exit_reason::write_exit_reason,
+    hypercall::{write_hypercall, write_hypercall_subop},
+};
+use crate::{config::CpuVendor, decoder::get_code_name};
+
+const HYPERCALL_OP_MASK: u32 = 0x000FFFFF;
+const HYPERCALL_ARGS_SHIFT: u32 = 20;
+
+/// Position of a field value within the extra words of an event.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Layout {
+    /// A whole 32-bit word.
+    Word(usize),
+    /// A 64-bit value split into a low and a high word.
+    Quad(usize),
+    /// A bit range of a single word.
+    Bits(usize, u32, u32),
+    /// An argument of a `pv:hypercall_v2` record, if traced. The first word holds
+    /// the hypercall number and, from bit 20, 2 bits per argument telling its size.
+    HypercallArg(usize),
+}
+
+/// How a field value is rendered into the info string.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Format {
+    Dec,
+    Signed,
+    Hex,
+    Nanos,
+    /// A VM exit reason, named after the CPU vendor.
+    ExitReason,
+    /// A hypercall number.
+    Hypercall,
+    /// The command of a multiplexed hypercall, whose number is in the first word.
+    HypercallSubop,
+    /// An event code, named as its records.
+    EventName,
+    /// A Linux task state, as the letter shown by `ps`.
+    TaskState,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Field {
+    pub(crate) name: &'static str,
+    pub(crate) layout: Layout,
+    pub(crate) format: Format,
+}
+
+impl Field {
+    pub(crate) const fn word(name: &'static str, index: usize, format: Format) -> Self {
+        Self {
+            name,
+            layout: Layout::Word(index),
+            format,
+        }
+    }
+
+    pub(crate) const fn quad(name: &'static str, index: usize, format: Format) -> Self {
+        Self {
+            name,
+            layout: Layout::Quad(index),
+            format,
+        }
+    }
+
+    pub(crate) const fn bits(
+        name: &'static str,
+        index: usize,
+        shift: u32,
+        width: u32,
+        format: Format,
+    ) -> Self {
+        Self {
+            name,
+            layout: Layout::Bits(index, shift, width),
+            format,
+        }
+    }
+
+    pub(crate) const fn arg(name: &'static str, index: usize, format: Format) -> Self {
+        Self {
+            name,
+            layout: Layout::HypercallArg(index),
+            format,
+        }
+    }
+
+    /// Reads the raw value of the field, if the event carries enough extra words.
+    pub(crate) fn read(&self, extra: &[Option<u32>; EVENT_EXTRA_CAPACITY]) -> Option<u64> {
+        let word = |index: usize| extra.get(index).copied().flatten().map(u64::from);
+
+        match self.layout {
+            Layout::Word(index) => word(index),
+            Layout::Quad(index) => Some(word(index)? | word(index + 1)? << 32),
+            Layout::Bits(index, shift, width) => Some((word(index)? >> shift) & ((1 << width) - 1)),
+            Layout::HypercallArg(arg) => {
+                let sizes = word(0)? >> HYPERCALL_ARGS_SHIFT;
+                let size = |arg: usize| (sizes >> (2 * arg) & 0x3) as usize;
+                let index = 1 + (0..arg).map(size).sum::<usize>();
+
+                match size(arg) {
+                    1 => word(index),
+                    2 => Some(word(index)? | word(index + 1)? << 32),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Reads the field as a 64-bit integer, sign-extending signed words.
+    pub(crate) fn read_int64(&self, extra: &[Option<u32>; EVENT_EXTRA_CAPACITY]) -> Option<i64> {
+        let value = self.read(extra)?;
+
+        match (self.format, self.layout) {
+            (Format::Signed, Layout::Word(_)) => Some(value as u32 as i32 as i64),
+            _ => Some(value as i64),
+        }
+    }
+
+    pub(crate) fn write_value(
+        &self,
+        out: &mut String,
+        value: u64,
+        extra: &[Option<u32>; EVENT_EXTRA_CAPACITY],
+        vendor: Option<CpuVendor>,
+    ) {
+        let _ = match self.format {
+            Format::Dec => write!(out, "{value}"),
+            Format::Signed => match self.layout {
+                Layout::Quad(_) => write!(out, "{}", value as i64),
+                _ => write!(out, "{}", value as u32 as i32),
+            },
+            Format::Hex => write!(out, "0x{value:X}"),
+            Format::Nanos => {
+                write_nanos(out, value);
+                Ok(())
+            }
+            Format::ExitReason => {
+                write_exit_reason(out, value as u32, vendor);
+                Ok(())
+            }
+            Format::Hypercall => {
+                write_hypercall(out, value as u32);
+                Ok(())
+            }
+            Format::HypercallSubop => {
+                let op = extra[0].unwrap_or_default() & HYPERCALL_OP_MASK;
+                write_hypercall_subop(out, op, value as u32);
+                Ok(())
+            }
+            Format::EventName => write!(out, "{}", get_code_name(EventCode::from(value as u32))),
+            Format::TaskState => match value {
+                0 => write!(out, "R"),
+                1 => write!(out, "S"),
+                2 => write!(out, "D"),
+                _ => write!(out, "0x{value:X}"),
+            },
+        };
+    }
+}
+
+/// Writes a duration in nanoseconds, in the largest unit up to milliseconds.
+pub(crate) fn write_nanos(out: &mut String, value: u64) {
+    let _ = match value {
+        0..=999 => write!(out, "{value}ns"),
+        1_000..=999_999 => write!(out, "{}.{:03}us", value / 1_000, value % 1_000),
+        _ => write!(out, "{}.{:03}ms", value / 1_000_000, value / 1_000 % 1_000),
+    };
+}
+
+// Common fields of the scheduler records
+pub(crate) const DOM: Field = Field::word("dom", 0, Format::Dec);
+pub(crate) const VCPU: Field = Field::word("vcpu", 1, Format::Dec);
+pub(crate) const PACKED_DOM: Field = Field::bits("dom", 0, 16, 16, Format::Dec);
+pub(crate) const PACKED_VCPU: Field = Field::bits("vcpu", 0, 0, 16, Format::Dec);
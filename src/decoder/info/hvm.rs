@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use fxhash::FxBuildHasher;
+
+use super::field::{Field, Format::*};
+
+const HVM_ENTRYEXIT_FIELDS: &[(u16, &[Field])] = &[
+    (
+        0x002,
+        &[
+            Field::word("exit_reason", 0, ExitReason),
+            Field::word("rip", 1, Hex),
+        ],
+    ),
+    (
+        0x102,
+        &[
+            Field::word("exit_reason", 0, ExitReason),
+            Field::quad("rip", 1, Hex),
+        ],
+    ),
+    (
+        0x402,
+        &[
+            Field::word("exit_reason", 0, ExitReason),
+            Field::word("rip", 1, Hex),
+        ],
+    ),
+    (
+        0x502,
+        &[
+            Field::word("exit_reason", 0, ExitReason),
+            Field::quad("rip", 1, Hex),
+        ],
+    ),
+];
+
+const HVM_HANDLER_FIELDS: &[(u16, &[Field])] = &[
+    (
+        0x001,
+        &[
+            Field::word("virt", 0, Hex),
+            Field::word("errorcode", 1, Hex),
+        ],
+    ),
+    (
+        0x101,
+        &[
+            Field::quad("virt", 0, Hex),
+            Field::word("errorcode", 2, Hex),
+        ],
+    ),
+    (
+        0x002,
+        &[
+            Field::word("errorcode", 0, Hex),
+            Field::word("virt", 1, Hex),
+        ],
+    ),
+    (
+        0x102,
+        &[
+            Field::word("errorcode", 0, Hex),
+            Field::quad("virt", 1, Hex),
+        ],
+    ),
+    (
+        0x003,
+        &[
+            Field::word("vector", 0, Hex),
+            Field::word("errorcode", 1, Hex),
+        ],
+    ),
+    (
+        0x004,
+        &[Field::word("vector", 0, Hex), Field::word("fake", 1, Dec)],
+    ),
+    (0x005, &[Field::word("vector", 0, Hex)]),
+    (
+        0x006,
+        &[Field::word("port", 0, Hex), Field::word("size", 1, Dec)],
+    ),
+    (
+        0x007,
+        &[Field::word("port", 0, Hex), Field::word("size", 1, Dec)],
+    ),
+    (
+        0x008,
+        &[Field::word("cr", 0, Dec), Field::word("value", 1, Hex)],
+    ),
+    (
+        0x108,
+        &[Field::word("cr", 0, Dec), Field::quad("value", 1, Hex)],
+    ),
+    (
+        0x009,
+        &[Field::word("cr", 0, Dec), Field::word("value", 1, Hex)],
+    ),
+    (
+        0x109,
+        &[Field::word("cr", 0, Dec), Field::quad("value", 1, Hex)],
+    ),
+    (
+        0x00C,
+        &[Field::word("msr", 0, Hex), Field::quad("value", 1, Hex)],
+    ),
+    (
+        0x00D,
+        &[Field::word("msr", 0, Hex), Field::quad("value", 1, Hex)],
+    ),
+    (
+        0x00E,
+        &[
+            Field::word("func", 0, Hex),
+            Field::word("eax", 1, Hex),
+            Field::word("ebx", 2, Hex),
+            Field::word("ecx", 3, Hex),
+            Field::word("edx", 4, Hex),
+        ],
+    ),
+    (0x00F, &[Field::word("vector", 0, Hex)]),
+    (0x012, &[Field::word("func", 0, Hypercall)]),
+    (0x013, &[Field::word("intpending", 0, Dec)]),
+    (
+        0x014,
+        &[Field::word("invlpga", 0, Dec), Field::word("virt", 1, Hex)],
+    ),
+    (
+        0x114,
+        &[Field::word("invlpga", 0, Dec), Field::quad("virt", 1, Hex)],
+    ),
+    (
+        0x016,
+        &[Field::word("port", 0, Hex), Field::word("data", 1, Hex)],
+    ),
+    (
+        0x216,
+        &[Field::word("port", 0, Hex), Field::word("data", 1, Hex)],
+    ),
+    (
+        0x017,
+        &[Field::word("addr", 0, Hex), Field::word("data", 1, Hex)],
+    ),
+    (
+        0x217,
+        &[Field::word("addr", 0, Hex), Field::word("data", 1, Hex)],
+    ),
+    (0x019, &[Field::word("value", 0, Hex)]),
+    (0x119, &[Field::quad("value", 0, Hex)]),
+    (0x01A, &[Field::quad("value", 0, Hex)]),
+    (0x020, &[Field::word("vector", 0, Hex)]),
+    (
+        0x021,
+        &[
+            Field::quad("gpa", 0, Hex),
+            Field::quad("mfn", 2, Hex),
+            Field::word("qual", 4, Hex),
+            Field::word("p2mt", 5, Dec),
+        ],
+    ),
+    (0x023, &[Field::word("vector", 0, Hex)]),
+    (0x024, &[Field::word("exit_qualification", 0, Hex)]),
+];
+
+lazy_static::lazy_static! {
+    static ref HVM_ENTRYEXIT_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = HVM_ENTRYEXIT_FIELDS.iter().copied().collect();
+    static ref HVM_HANDLER_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = HVM_HANDLER_FIELDS.iter().copied().collect();
+}
+
+pub(super) fn get_hvm_fields(sub: u32, minor: u16) -> Option<&'static [Field]> {
+    match sub {
+        0x1 => HVM_ENTRYEXIT_MAP.get(&minor).copied(),
+        0x2 => HVM_HANDLER_MAP.get(&minor).copied(),
+        _ => None,
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use fxhash::FxBuildHasher;
+
+use super::field::{Field, Format::*};
+
+const HW_PM_FIELDS: &[(u16, &[Field])] = &[
+    (
+        0x001,
+        &[
+            Field::word("old_mhz", 0, Dec),
+            Field::word("new_mhz", 1, Dec),
+        ],
+    ),
+    (
+        0x002,
+        &[
+            Field::word("cstate", 0, Dec),
+            Field::word("tsc", 1, Hex),
+            Field::word("expected", 2, Dec),
+            Field::word("predicted", 3, Dec),
+        ],
+    ),
+    (
+        0x003,
+        &[Field::word("cstate", 0, Dec), Field::word("tsc", 1, Hex)],
+    ),
+];
+
+const HW_IRQ_FIELDS: &[(u16, &[Field])] = &[
+    (
+        0x001,
+        &[
+            Field::word("irq", 0, Dec),
+            Field::word("vector", 1, Hex),
+            Field::word("cpu", 2, Dec),
+        ],
+    ),
+    (
+        0x002,
+        &[
+            Field::word("irq", 0, Dec),
+            Field::word("vector", 1, Hex),
+            Field::word("cpu", 2, Dec),
+        ],
+    ),
+    (
+        0x003,
+        &[Field::word("irq", 0, Dec), Field::word("vector", 1, Hex)],
+    ),
+    (
+        0x004,
+        &[Field::word("irq", 0, Dec), Field::word("vector", 1, Hex)],
+    ),
+    (
+        0x005,
+        &[Field::word("irq", 0, Dec), Field::word("vector", 1, Hex)],
+    ),
+    (
+        0x006,
+        &[Field::word("irq", 0, Dec), Field::word("vector", 1, Hex)],
+    ),
+    (
+        0x007,
+        &[Field::word("vector", 0, Hex), Field::word("cpu", 1, Dec)],
+    ),
+    (
+        0x008,
+        &[
+            Field::word("irq", 0, Dec),
+            Field::word("tsc_in", 1, Hex),
+            Field::word("tsc_out", 2, Hex),
+        ],
+    ),
+];
+
+lazy_static::lazy_static! {
+    static ref HW_PM_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = HW_PM_FIELDS.iter().copied().collect();
+    static ref HW_IRQ_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = HW_IRQ_FIELDS.iter().copied().collect();
+}
+
+pub(super) fn get_hw_fields(sub: u32, minor: u16) -> Option<&'static [Field]> {
+    match sub {
+        0x1 => HW_PM_MAP.get(&minor).copied(),
+        0x2 => HW_IRQ_MAP.get(&minor).copied(),
+        _ => None,
+    }
+}
@@ -0,0 +1,292 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use fxhash::FxBuildHasher;
+
+/// Selects the command of a `memory_op`, whose upper bits carry its continuation.
+const MEMORY_OP_CMD_MASK: u32 = 0x3F;
+const HYPERVISOR_MEMORY_OP: u32 = 12;
+
+// Hypercall numbers, from `xen/include/public/xen.h`
+const HYPERCALL_NAMES: &[(u32, &str)] = &[
+    (0, "set_trap_table"),
+    (1, "mmu_update"),
+    (2, "set_gdt"),
+    (3, "stack_switch"),
+    (4, "set_callbacks"),
+    (5, "fpu_taskswitch"),
+    (6, "sched_op_compat"),
+    (7, "platform_op"),
+    (8, "set_debugreg"),
+    (9, "get_debugreg"),
+    (10, "update_descriptor"),
+    (12, "memory_op"),
+    (13, "multicall"),
+    (14, "update_va_mapping"),
+    (15, "set_timer_op"),
+    (16, "event_channel_op_compat"),
+    (17, "xen_version"),
+    (18, "console_io"),
+    (19, "physdev_op_compat"),
+    (20, "grant_table_op"),
+    (21, "vm_assist"),
+    (22, "update_va_mapping_otherdomain"),
+    (23, "iret"),
+    (24, "vcpu_op"),
+    (25, "set_segment_base"),
+    (26, "mmuext_op"),
+    (27, "xsm_op"),
+    (28, "nmi_op"),
+    (29, "sched_op"),
+    (30, "callback_op"),
+    (31, "xenoprof_op"),
+    (32, "event_channel_op"),
+    (33, "physdev_op"),
+    (34, "hvm_op"),
+    (35, "sysctl"),
+    (36, "domctl"),
+    (37, "kexec_op"),
+    (38, "tmem_op"),
+    (39, "argo_op"),
+    (40, "xenpmu_op"),
+    (41, "dm_op"),
+    (42, "hypfs_op"),
+    (48, "mca"),
+    (49, "paging_domctl_cont"),
+];
+
+// Commands of the multiplexed hypercalls, passed as their first argument
+const SCHED_OPS: &[(u32, &str)] = &[
+    (0, "yield"),
+    (1, "block"),
+    (2, "shutdown"),
+    (3, "poll"),
+    (4, "remote_shutdown"),
+    (5, "shutdown_code"),
+    (6, "watchdog"),
+    (7, "pin_override"),
+];
+
+const MEMORY_OPS: &[(u32, &str)] = &[
+    (0, "increase_reservation"),
+    (1, "decrease_reservation"),
+    (2, "maximum_ram_page"),
+    (3, "current_reservation"),
+    (4, "maximum_reservation"),
+    (5, "machphys_mfn_list"),
+    (6, "populate_physmap"),
+    (7, "add_to_physmap"),
+    (9, "memory_map"),
+    (10, "machine_memory_map"),
+    (11, "exchange"),
+    (12, "machphys_mapping"),
+    (13, "set_memory_map"),
+    (14, "maximum_gpfn"),
+    (15, "remove_from_physmap"),
+    (16, "set_pod_target"),
+    (17, "get_pod_target"),
+    (18, "get_sharing_freed_pages"),
+    (19, "get_sharing_shared_pages"),
+    (20, "paging_op"),
+    (21, "access_op"),
+    (22, "sharing_op"),
+    (23, "add_to_physmap_batch"),
+    (24, "claim_pages"),
+    (25, "machphys_compat_mfn_list"),
+    (26, "get_vnumainfo"),
+    (27, "reserved_device_memory_map"),
+    (28, "acquire_resource"),
+];
+
+const XEN_VERSION_OPS: &[(u32, &str)] = &[
+    (0, "version"),
+    (1, "extraversion"),
+    (2, "compile_info"),
+    (3, "capabilities"),
+    (4, "changeset"),
+    (5, "platform_parameters"),
+    (6, "get_features"),
+    (7, "pagesize"),
+    (8, "guest_handle"),
+    (9, "commandline"),
+    (10, "build_id"),
+];
+
+const CONSOLE_IO_OPS: &[(u32, &str)] = &[(0, "write"), (1, "read")];
+
+const GRANT_TABLE_OPS: &[(u32, &str)] = &[
+    (0, "map_grant_ref"),
+    (1, "unmap_grant_ref"),
+    (2, "setup_table"),
+    (3, "dump_table"),
+    (4, "transfer"),
+    (5, "copy"),
+    (6, "query_size"),
+    (7, "unmap_and_replace"),
+    (8, "set_version"),
+    (9, "get_status_frames"),
+    (10, "get_version"),
+    (11, "swap_grant_ref"),
+    (12, "cache_flush"),
+];
+
+const VM_ASSIST_OPS: &[(u32, &str)] = &[(0, "enable"), (1, "disable")];
+
+const VCPU_OPS: &[(u32, &str)] = &[
+    (0, "initialise"),
+    (1, "up"),
+    (2, "down"),
+    (3, "is_up"),
+    (4, "get_runstate_info"),
+    (5, "register_runstate_memory_area"),
+    (6, "set_periodic_timer"),
+    (7, "stop_periodic_timer"),
+    (8, "set_singleshot_timer"),
+    (9, "stop_singleshot_timer"),
+    (10, "register_vcpu_info"),
+    (11, "send_nmi"),
+    (12, "get_physid"),
+    (13, "register_vcpu_time_memory_area"),
+];
+
+const CALLBACK_OPS: &[(u32, &str)] = &[(0, "register"), (1, "unregister")];
+
+const EVENT_CHANNEL_OPS: &[(u32, &str)] = &[
+    (0, "bind_interdomain"),
+    (1, "bind_virq"),
+    (2, "bind_pirq"),
+    (3, "close"),
+    (4, "send"),
+    (5, "status"),
+    (6, "alloc_unbound"),
+    (7, "bind_ipi"),
+    (8, "bind_vcpu"),
+    (9, "unmask"),
+    (10, "reset"),
+    (11, "init_control"),
+    (12, "expand_array"),
+    (13, "set_priority"),
+    (14, "reset_cont"),
+];
+
+const PHYSDEV_OPS: &[(u32, &str)] = &[
+    (5, "irq_status_query"),
+    (6, "set_iopl"),
+    (7, "set_iobitmap"),
+    (8, "apic_read"),
+    (9, "apic_write"),
+    (10, "alloc_irq_vector"),
+    (11, "free_irq_vector"),
+    (12, "eoi"),
+    (13, "map_pirq"),
+    (14, "unmap_pirq"),
+    (15, "manage_pci_add"),
+    (16, "manage_pci_remove"),
+    (17, "pirq_eoi_gmfn_v1"),
+    (19, "restore_msi"),
+    (20, "manage_pci_add_ext"),
+    (21, "setup_gsi"),
+    (23, "get_free_pirq"),
+    (24, "pci_mmcfg_reserved"),
+    (25, "pci_device_add"),
+    (26, "pci_device_remove"),
+    (27, "restore_msi_ext"),
+    (28, "pirq_eoi_gmfn_v2"),
+    (29, "dbgp_op"),
+    (30, "prepare_msix"),
+    (31, "release_msix"),
+    (32, "pci_device_reset"),
+];
+
+const HVM_OPS: &[(u32, &str)] = &[
+    (0, "set_param"),
+    (1, "get_param"),
+    (2, "set_pci_intx_level"),
+    (3, "set_isa_irq_level"),
+    (4, "set_pci_link_route"),
+    (5, "flush_tlbs"),
+    (6, "track_dirty_vram"),
+    (7, "modified_memory"),
+    (8, "set_mem_type"),
+    (9, "pagetable_dying"),
+    (10, "get_time"),
+    (11, "xentrace"),
+    (12, "set_mem_access"),
+    (13, "get_mem_access"),
+    (14, "inject_trap"),
+    (15, "get_mem_type"),
+    (16, "inject_msi"),
+    (23, "set_evtchn_upcall_vector"),
+    (24, "guest_request_vm_event"),
+    (25, "altp2m"),
+];
+
+const HYPERCALL_SUBOPS: &[(u32, &[(u32, &str)])] = &[
+    (6, SCHED_OPS),
+    (12, MEMORY_OPS),
+    (17, XEN_VERSION_OPS),
+    (18, CONSOLE_IO_OPS),
+    (20, GRANT_TABLE_OPS),
+    (21, VM_ASSIST_OPS),
+    (24, VCPU_OPS),
+    (29, SCHED_OPS),
+    (30, CALLBACK_OPS),
+    (32, EVENT_CHANNEL_OPS),
+    (33, PHYSDEV_OPS),
+    (34, HVM_OPS),
+];
+
+lazy_static::lazy_static! {
+    static ref HYPERCALL_MAP: HashMap<u32, &'static str, FxBuildHasher> = HYPERCALL_NAMES.iter().copied().collect();
+    static ref SUBOP_MAP: HashMap<(u32, u32), &'static str, FxBuildHasher> = HYPERCALL_SUBOPS
+        .iter()
+        .flat_map(|(op, subops)| subops.iter().map(move |(subop, name)| ((*op, *subop), *name)))
+        .collect();
+}
+
+/// Writes the name of a hypercall, or its number if unknown.
+pub(crate) fn write_hypercall(out: &mut String, op: u32) {
+    let _ = match HYPERCALL_MAP.get(&op) {
+        Some(name) => write!(out, "{name}"),
+        None => write!(out, "{op}"),
+    };
+}
+
+/// Writes the name of the command of a multiplexed hypercall, or its number if unknown.
+pub(crate) fn write_hypercall_subop(out: &mut String, op: u32, subop: u32) {
+    let subop = match op {
+        HYPERVISOR_MEMORY_OP => subop & MEMORY_OP_CMD_MASK,
+        _ => subop,
+    };
+
+    let _ = match SUBOP_MAP.get(&(op, subop)) {
+        Some(name) => write!(out, "{name}"),
+        None => write!(out, "{subop}"),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_hypercall, write_hypercall_subop};
+
+    #[test]
+    fn hypercall_names() {
+        let hypercall = |op| {
+            let mut out = String::new();
+            write_hypercall(&mut out, op);
+            out
+        };
+        let subop = |op, subop| {
+            let mut out = String::new();
+            write_hypercall_subop(&mut out, op, subop);
+            out
+        };
+
+        assert_eq!(hypercall(1), "mmu_update");
+        assert_eq!(hypercall(32), "event_channel_op");
+        assert_eq!(hypercall(99), "99");
+        assert_eq!(subop(29, 1), "block");
+        assert_eq!(subop(20, 5), "copy");
+        assert_eq!(subop(12, 0x100 | 6), "populate_physmap");
+        assert_eq!(subop(1, 2), "2");
+    }
+}
@@ -1,6 +1,295 @@
-use xentrace_parser::record::Event;
+mod exit_reason;
+mod field;
+mod hvm;
+mod hw;
+mod hypercall;
+mod sched;
 
-pub(crate) fn get_record_info(event: &Event) -> String {
+use std::collections::HashMap;
+
+use fxhash::FxBuildHasher;
+use xentrace_parser::record::EventCode;
+
+pub(crate) use self::{
+    exit_reason::{detect_cpu_vendor, write_exit_reason},
+    field::{write_nanos, Field},
+};
+use self::{field::Format::*, hvm::get_hvm_fields, hw::get_hw_fields, sched::get_sched_fields};
+use crate::{config::CpuVendor, ffi::xen::trace::*, store::Event};
+
+const DOM0OP_FIELDS: &[(u16, &[Field])] = &[
+    (0x001, &[Field::word("dom", 0, Dec)]),
+    (0x002, &[Field::word("dom", 0, Dec)]),
+];
+
+const GEN_FIELDS: &[(u16, &[Field])] = &[
+    (
+        0x001,
+        &[
+            Field::word("lost", 0, Dec),
+            Field::bits("dom", 1, 0, 16, Dec),
+            Field::bits("vcpu", 1, 16, 16, Dec),
+            Field::quad("first_tsc", 2, Dec),
+        ],
+    ),
+    (
+        0x003,
+        &[Field::word("cpu", 0, Dec), Field::word("size", 1, Dec)],
+    ),
+];
+
+const SYNTH_FIELDS: &[(u16, &[Field])] = &[
+    (
+        0x001,
+        &[
+            Field::word("prev_pid", 0, Dec),
+            Field::word("prev_state", 6, TaskState),
+            Field::word("next_pid", 1, Dec),
+            Field::word("prev_dom", 2, Dec),
+            Field::word("prev_vcpu", 3, Dec),
+            Field::word("next_dom", 4, Dec),
+            Field::word("next_vcpu", 5, Dec),
+        ],
+    ),
+    (
+        0x002,
+        &[
+            Field::word("pid", 0, Dec),
+            Field::word("dom", 1, Dec),
+            Field::word("vcpu", 2, Dec),
+        ],
+    ),
+    (
+        0x003,
+        &[
+            Field::word("exit_reason", 0, ExitReason),
+            Field::quad("latency", 1, Nanos),
+            Field::word("handlers", 3, Dec),
+            Field::word("handler", 4, EventName),
+        ],
+    ),
+];
+
+const MEM_FIELDS: &[(u16, &[Field])] = &[
+    (0x001, &[Field::word("dom", 0, Dec)]),
+    (0x002, &[Field::word("dom", 0, Dec)]),
+    (0x003, &[Field::word("dom", 0, Dec)]),
+    (
+        0x004,
+        &[
+            Field::quad("gfn", 0, Hex),
+            Field::quad("mfn", 2, Hex),
+            Field::word("p2mt", 4, Dec),
+            Field::bits("dom", 5, 0, 16, Dec),
+            Field::bits("order", 5, 16, 16, Dec),
+        ],
+    ),
+    (
+        0x005,
+        &[
+            Field::quad("gfn", 0, Hex),
+            Field::bits("dom", 2, 0, 16, Dec),
+            Field::bits("order", 2, 16, 16, Dec),
+        ],
+    ),
+    (
+        0x010,
+        &[
+            Field::quad("gfn", 0, Hex),
+            Field::quad("mfn", 2, Hex),
+            Field::bits("dom", 4, 0, 16, Dec),
+            Field::bits("order", 4, 16, 16, Dec),
+        ],
+    ),
+    (
+        0x011,
+        &[
+            Field::quad("gfn", 0, Hex),
+            Field::quad("mfn", 2, Hex),
+            Field::bits("dom", 4, 0, 16, Dec),
+            Field::bits("order", 4, 16, 16, Dec),
+        ],
+    ),
+    (
+        0x012,
+        &[
+            Field::quad("gfn", 0, Hex),
+            Field::bits("dom", 2, 0, 16, Dec),
+        ],
+    ),
+];
+
+/// Fields of the `pv:hypercall_v2` and `pv:hypercall_subcall` records,
+/// the first traced argument of the multiplexed hypercalls being their command.
+const HYPERCALL_V2_FIELDS: &[Field] = &[
+    Field::bits("op", 0, 0, 20, Hypercall),
+    Field::arg("subop", 0, HypercallSubop),
+    Field::arg("arg1", 1, Hex),
+    Field::arg("arg2", 2, Hex),
+    Field::arg("arg3", 3, Hex),
+    Field::arg("arg4", 4, Hex),
+    Field::arg("arg5", 5, Hex),
+];
+
+const PV_FIELDS: &[(u16, &[Field])] = &[
+    (
+        0x001,
+        &[Field::word("eip", 0, Hex), Field::word("eax", 1, Hypercall)],
+    ),
+    (
+        0x101,
+        &[Field::quad("rip", 0, Hex), Field::word("eax", 2, Hypercall)],
+    ),
+    (
+        0x003,
+        &[
+            Field::word("eip", 0, Hex),
+            Field::bits("trapnr", 1, 0, 15, Dec),
+            Field::bits("error_code", 1, 16, 16, Hex),
+        ],
+    ),
+    (
+        0x103,
+        &[
+            Field::quad("rip", 0, Hex),
+            Field::bits("trapnr", 2, 0, 15, Dec),
+            Field::bits("error_code", 2, 16, 16, Hex),
+        ],
+    ),
+    (
+        0x004,
+        &[
+            Field::word("eip", 0, Hex),
+            Field::word("addr", 1, Hex),
+            Field::word("error_code", 2, Hex),
+        ],
+    ),
+    (
+        0x104,
+        &[
+            Field::quad("rip", 0, Hex),
+            Field::quad("addr", 2, Hex),
+            Field::word("error_code", 4, Hex),
+        ],
+    ),
+    (0x005, &[Field::word("eip", 0, Hex)]),
+    (0x105, &[Field::quad("rip", 0, Hex)]),
+    (0x006, &[Field::word("eip", 0, Hex)]),
+    (0x106, &[Field::quad("rip", 0, Hex)]),
+    (0x007, &[Field::word("eip", 0, Hex)]),
+    (0x107, &[Field::quad("rip", 0, Hex)]),
+    (
+        0x009,
+        &[Field::word("eip", 0, Hex), Field::word("addr", 1, Hex)],
+    ),
+    (
+        0x109,
+        &[Field::quad("rip", 0, Hex), Field::quad("addr", 2, Hex)],
+    ),
+    (
+        0x00A,
+        &[Field::word("eip", 0, Hex), Field::word("offset", 1, Hex)],
+    ),
+    (
+        0x10A,
+        &[Field::quad("rip", 0, Hex), Field::quad("offset", 2, Hex)],
+    ),
+    (
+        0x00B,
+        &[
+            Field::quad("pte", 0, Hex),
+            Field::word("addr", 2, Hex),
+            Field::word("eip", 3, Hex),
+        ],
+    ),
+    (
+        0x10B,
+        &[
+            Field::quad("pte", 0, Hex),
+            Field::quad("addr", 2, Hex),
+            Field::quad("rip", 4, Hex),
+        ],
+    ),
+    (
+        0x00C,
+        &[
+            Field::quad("pte", 0, Hex),
+            Field::word("addr", 2, Hex),
+            Field::word("eip", 3, Hex),
+        ],
+    ),
+    (
+        0x10C,
+        &[
+            Field::quad("pte", 0, Hex),
+            Field::quad("addr", 2, Hex),
+            Field::quad("rip", 4, Hex),
+        ],
+    ),
+    (0x00D, HYPERCALL_V2_FIELDS),
+    (0x00E, HYPERCALL_V2_FIELDS),
+];
+
+lazy_static::lazy_static! {
+    static ref DOM0OP_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = DOM0OP_FIELDS.iter().copied().collect();
+    static ref GEN_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = GEN_FIELDS.iter().copied().collect();
+    static ref MEM_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = MEM_FIELDS.iter().copied().collect();
+    static ref PV_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = PV_FIELDS.iter().copied().collect();
+    static ref SYNTH_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = SYNTH_FIELDS.iter().copied().collect();
+}
+
+pub(crate) fn get_event_fields(ecode: EventCode) -> Option<&'static [Field]> {
+    let main = ecode.main();
+    let sub = ecode.sub();
+    let minor = ecode.minor() as u16;
+
+    match main {
+        TRC_HVM => get_hvm_fields(sub, minor),
+        TRC_HW => get_hw_fields(sub, minor),
+        TRC_SCHED => get_sched_fields(sub, minor),
+
+        TRC_DOM0OP => DOM0OP_MAP.get(&minor).copied(),
+        TRC_GEN => GEN_MAP.get(&minor).copied(),
+        TRC_MEM => MEM_MAP.get(&minor).copied(),
+        TRC_PV => PV_MAP.get(&minor).copied(),
+        TRC_SYNTH => SYNTH_MAP.get(&minor).copied(),
+
+        _ => None,
+    }
+}
+
+pub(crate) fn read_event_field(event: &Event, name: &str) -> Option<i64> {
+    get_event_fields(event.code())?
+        .iter()
+        .find(|field| field.name == name)
+        .and_then(|field| field.read_int64(event.extra()))
+}
+
+/// Renders the fields of an event, naming the exit reasons after the given CPU vendor.
+pub(crate) fn get_record_info(event: &Event, vendor: Option<CpuVendor>) -> String {
+    let mut info = String::new();
+
+    for field in get_event_fields(event.code()).unwrap_or_default() {
+        let Some(value) = field.read(event.extra()) else {
+            continue;
+        };
+
+        if !info.is_empty() {
+            info.push(' ');
+        }
+
+        info.push_str(field.name);
+        info.push('=');
+        field.write_value(&mut info, value, event.extra(), vendor);
+    }
+
+    match info.is_empty() {
+        true => get_record_extra(event),
+        false => info,
+    }
+}
+
+pub(crate) fn get_record_extra(event: &Event) -> String {
     event
         .extra()
         .iter()
@@ -8,3 +297,88 @@ pub(crate) fn get_record_info(event: &Event) -> String {
         .collect::<Vec<_>>()
         .join(", ")
 }
+
+#[cfg(test)]
+mod test {
+    use super::{get_record_info, read_event_field};
+    use crate::{config::CpuVendor, util::test::trace_records};
+
+    #[test]
+    fn labelled_fields() {
+        let trace = trace_records(&[
+            (0x0002800E, 10, &[1, 0, 123_456]),
+            (0x00081102, 20, &[0x30, 0xDEAD_BEEF, 0x1]),
+            (0x00021011, 30, &[0x0003_0002]),
+        ]);
+
+        assert_eq!(
+            get_record_info(trace[0].event(), None),
+            "dom=1 vcpu=0 runtime=123.456us"
+        );
+        assert_eq!(
+            get_record_info(trace[1].event(), None),
+            "exit_reason=0x30 rip=0x1DEADBEEF"
+        );
+        assert_eq!(get_record_info(trace[2].event(), None), "dom=3 vcpu=2");
+        assert_eq!(
+            get_record_info(trace[1].event(), Some(CpuVendor::Intel)),
+            "exit_reason=EPT_VIOLATION rip=0x1DEADBEEF"
+        );
+    }
+
+    #[test]
+    fn integer_fields() {
+        let trace = trace_records(&[
+            (0x00022203, 10, &[0x0001_0002, -500i32 as u32, 10, 20]),
+            (0x00081002, 20, &[0x30, 0x1000]),
+        ]);
+
+        assert_eq!(read_event_field(trace[0].event(), "dom"), Some(1));
+        assert_eq!(read_event_field(trace[0].event(), "credit"), Some(-500));
+        assert_eq!(
+            read_event_field(trace[1].event(), "exit_reason"),
+            Some(0x30)
+        );
+        assert_eq!(read_event_field(trace[1].event(), "missing"), None);
+    }
+
+    #[test]
+    fn hypercall_fields() {
+        let trace = trace_records(&[
+            (0x0020100D, 10, &[1 << 24 | 1 << 20 | 20, 5, 3]), // grant_table_op(copy, _, 3)
+            (0x0020100D, 20, &[1 << 22 | 26, 8]),              // mmuext_op(_, 8)
+            (0x0020100E, 30, &[1 << 20 | 29, 1]),              // sched_op(block)
+            (0x00201001, 40, &[0xC000, 24]),
+        ]);
+
+        assert_eq!(
+            get_record_info(trace[0].event(), None),
+            "op=grant_table_op subop=copy arg2=0x3"
+        );
+        assert_eq!(
+            get_record_info(trace[1].event(), None),
+            "op=mmuext_op arg1=0x8"
+        );
+        assert_eq!(
+            get_record_info(trace[2].event(), None),
+            "op=sched_op subop=block"
+        );
+        assert_eq!(
+            get_record_info(trace[3].event(), None),
+            "eip=0xC000 eax=vcpu_op"
+        );
+        assert_eq!(read_event_field(trace[0].event(), "op"), Some(20));
+        assert_eq!(read_event_field(trace[0].event(), "subop"), Some(5));
+        assert_eq!(read_event_field(trace[1].event(), "subop"), None);
+    }
+
+    #[test]
+    fn unknown_fields_fallback() {
+        let trace = trace_records(&[(0x0040F001, 10, &[0xAB, 0xCD])]);
+
+        assert_eq!(
+            get_record_info(trace[0].event(), None),
+            "0x000000AB, 0x000000CD"
+        );
+    }
+}
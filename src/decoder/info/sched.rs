@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use fxhash::FxBuildHasher;
+
+use super::field::{Field, Format::*, DOM, PACKED_DOM, PACKED_VCPU, VCPU};
+
+const RUNSTATE_FIELDS: &[Field] = &[PACKED_DOM, PACKED_VCPU];
+
+const SCHED_MIN_FIELDS: &[(u16, &[Field])] = &[
+    (0x002, RUNSTATE_FIELDS),
+    (0x011, RUNSTATE_FIELDS),
+    (0x021, RUNSTATE_FIELDS),
+    (0x031, RUNSTATE_FIELDS),
+    (0x101, RUNSTATE_FIELDS),
+    (0x121, RUNSTATE_FIELDS),
+    (0x131, RUNSTATE_FIELDS),
+    (0x201, RUNSTATE_FIELDS),
+    (0x211, RUNSTATE_FIELDS),
+    (0x231, RUNSTATE_FIELDS),
+    (0x301, RUNSTATE_FIELDS),
+    (0x311, RUNSTATE_FIELDS),
+    (0x321, RUNSTATE_FIELDS),
+];
+
+const SCHED_CLASS_FIELDS: &[(u16, &[Field])] = &[
+    (0x002, &[DOM, VCPU, Field::word("active", 2, Dec)]),
+    (0x003, &[DOM, VCPU, Field::word("active", 2, Dec)]),
+    (
+        0x004,
+        &[
+            Field::word("peer_cpu", 0, Dec),
+            Field::word("dom", 1, Dec),
+            Field::word("vcpu", 2, Dec),
+        ],
+    ),
+    (0x005, &[DOM, VCPU, Field::word("cpu", 2, Dec)]),
+    (0x006, &[Field::word("cpu", 0, Dec)]),
+    (0x007, &[DOM, VCPU]),
+    (0x008, &[DOM, VCPU]),
+    (
+        0x00A,
+        &[PACKED_DOM, PACKED_VCPU, Field::word("runtime", 1, Dec)],
+    ),
+    (
+        0x00B,
+        &[
+            Field::word("peer_cpu", 0, Dec),
+            Field::word("checked", 1, Dec),
+        ],
+    ),
+    //
+    (
+        0x202,
+        &[PACKED_DOM, PACKED_VCPU, Field::word("pos", 1, Dec)],
+    ),
+    (
+        0x203,
+        &[
+            PACKED_DOM,
+            PACKED_VCPU,
+            Field::word("credit", 1, Signed),
+            Field::word("budget", 2, Signed),
+            Field::word("delta", 3, Signed),
+        ],
+    ),
+    (
+        0x205,
+        &[
+            PACKED_DOM,
+            PACKED_VCPU,
+            Field::word("credit", 1, Signed),
+            Field::word("score", 2, Signed),
+        ],
+    ),
+    (0x206, &[Field::word("cpu", 0, Dec)]),
+    (
+        0x207,
+        &[
+            PACKED_DOM,
+            PACKED_VCPU,
+            Field::word("credit_start", 1, Signed),
+            Field::word("credit_end", 2, Signed),
+        ],
+    ),
+    (
+        0x20A,
+        &[PACKED_DOM, PACKED_VCPU, Field::word("rq_id", 1, Dec)],
+    ),
+    //
+    (0x801, &[Field::bits("cpu", 0, 0, 16, Dec)]),
+    (
+        0x802,
+        &[
+            PACKED_DOM,
+            PACKED_VCPU,
+            Field::quad("deadline", 1, Nanos),
+            Field::quad("budget", 3, Nanos),
+        ],
+    ),
+    (
+        0x803,
+        &[
+            PACKED_DOM,
+            PACKED_VCPU,
+            Field::quad("budget", 1, Nanos),
+            Field::word("delta", 3, Signed),
+        ],
+    ),
+    (
+        0x804,
+        &[
+            PACKED_DOM,
+            PACKED_VCPU,
+            Field::quad("deadline", 1, Nanos),
+            Field::quad("budget", 3, Nanos),
+        ],
+    ),
+    //
+    (
+        0xA01,
+        &[PACKED_DOM, PACKED_VCPU, Field::bits("cpu", 1, 0, 16, Dec)],
+    ),
+    (
+        0xA02,
+        &[PACKED_DOM, PACKED_VCPU, Field::word("cpu", 1, Dec)],
+    ),
+    (
+        0xA03,
+        &[PACKED_DOM, PACKED_VCPU, Field::word("cpu", 1, Dec)],
+    ),
+    (
+        0xA04,
+        &[
+            PACKED_DOM,
+            PACKED_VCPU,
+            Field::bits("new_cpu", 1, 0, 16, Dec),
+            Field::bits("old_cpu", 1, 16, 16, Dec),
+        ],
+    ),
+];
+
+const SCHED_VERBOSE_FIELDS: &[(u16, &[Field])] = &[
+    (0x001, &[DOM]),
+    (0x002, &[DOM]),
+    (0x003, &[DOM, VCPU]),
+    (0x004, &[DOM, VCPU]),
+    (0x005, &[DOM, VCPU]),
+    (0x006, &[DOM, VCPU]),
+    (0x007, &[DOM, VCPU, Field::word("reason", 2, Dec)]),
+    (0x009, &[DOM]),
+    (
+        0x00A,
+        &[
+            Field::word("prev_dom", 0, Dec),
+            Field::word("prev_vcpu", 1, Dec),
+            Field::word("next_dom", 2, Dec),
+            Field::word("next_vcpu", 3, Dec),
+        ],
+    ),
+    (0x00D, &[DOM, VCPU]),
+    (0x00E, &[DOM, VCPU, Field::word("runtime", 2, Nanos)]),
+    (
+        0x00F,
+        &[
+            DOM,
+            VCPU,
+            Field::word("wait_time", 2, Nanos),
+            Field::word("slice", 3, Nanos),
+        ],
+    ),
+    (0x010, &[DOM, VCPU, Field::word("reason", 2, Dec)]),
+    (
+        0x011,
+        &[
+            DOM,
+            VCPU,
+            Field::word("runtime", 2, Nanos),
+            Field::word("slice", 3, Nanos),
+        ],
+    ),
+];
+
+lazy_static::lazy_static! {
+    static ref SCHED_MIN_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = SCHED_MIN_FIELDS.iter().copied().collect();
+    static ref SCHED_CLASS_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = SCHED_CLASS_FIELDS.iter().copied().collect();
+    static ref SCHED_VERBOSE_MAP: HashMap<u16, &'static [Field], FxBuildHasher> = SCHED_VERBOSE_FIELDS.iter().copied().collect();
+}
+
+pub(super) fn get_sched_fields(sub: u32, minor: u16) -> Option<&'static [Field]> {
+    match sub {
+        0x1 => SCHED_MIN_MAP.get(&minor).copied(),
+        0x2 => SCHED_CLASS_MAP.get(&minor).copied(),
+        0x8 => SCHED_VERBOSE_MAP.get(&minor).copied(),
+        _ => None,
+    }
+}
@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use super::get_record_info;
+use crate::{
+    ffi::xen::trace::{TRC_LOST_RECORDS, TRC_TRACE_WRAP_BUFFER},
+    log,
+    store::Record,
+};
+
+/// Records missing from the trace, as reported by the `gen:lost_records`
+/// and `gen:wrap_buffer` records of each CPU buffer.
+#[derive(Debug, Default)]
+pub(crate) struct DataLoss {
+    /// Number of lost records, keyed by CPU.
+    lost: BTreeMap<u32, u64>,
+    /// Number of buffer wraps, keyed by CPU.
+    wraps: BTreeMap<u32, u64>,
+}
+
+impl DataLoss {
+    pub(crate) fn add(&mut self, record: &Record) {
+        let cpu = record.cpu();
+
+        match record.event().code().value() {
+            TRC_LOST_RECORDS => {
+                let count = get_lost_count(record).unwrap_or_default();
+                *self.lost.entry(cpu).or_default() += u64::from(count);
+            }
+            TRC_TRACE_WRAP_BUFFER => *self.wraps.entry(cpu).or_default() += 1,
+            _ => {}
+        }
+    }
+
+    pub(crate) fn summary(&self) -> Option<String> {
+        if self.lost.is_empty() && self.wraps.is_empty() {
+            return None;
+        }
+
+        let mut summary = String::new();
+        if !self.lost.is_empty() {
+            let total = self.lost.values().sum::<u64>();
+            let _ = write!(summary, "{total} records lost (");
+            write_per_cpu(&mut summary, &self.lost);
+            summary.push_str("), idle gaps before gen:lost_records are missing data");
+        }
+
+        if !self.wraps.is_empty() {
+            if !summary.is_empty() {
+                summary.push_str("; ");
+            }
+
+            let total = self.wraps.values().sum::<u64>();
+            let _ = write!(summary, "{total} buffer wraps (");
+            write_per_cpu(&mut summary, &self.wraps);
+            summary.push(')');
+        }
+
+        Some(summary)
+    }
+
+    /// Warns about the records missing from the loaded trace, if any.
+    pub(crate) fn report(&self) {
+        if let Some(summary) = self.summary() {
+            log!("Warning: {summary}");
+        }
+    }
+}
+
+fn write_per_cpu(out: &mut String, counts: &BTreeMap<u32, u64>) {
+    for (index, (cpu, count)) in counts.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+
+        let _ = write!(out, "CPU {cpu}: {count}");
+    }
+}
+
+fn get_lost_count(record: &Record) -> Option<u32> {
+    record.event().extra()[0]
+}
+
+/// Info of a `gen:lost_records` record, led by the CPU whose buffer lost them.
+pub(crate) fn get_loss_info(record: &Record) -> Option<String> {
+    (record.event().code() == TRC_LOST_RECORDS).then(|| {
+        format!(
+            "cpu={} {}",
+            record.cpu(),
+            get_record_info(record.event(), None)
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_loss_info, DataLoss};
+    use crate::{store::TraceStore, util::test::trace_bytes};
+
+    #[test]
+    fn lost_records_summary() {
+        let trace = TraceStore::from_bytes(&trace_bytes(&[
+            (0x0001F001, 10, &[120, 0x0000_0001, 5, 0]),
+            (0x0001F003, 20, &[1]),
+            (0x0001F001, 30, &[30, 0, 25, 0]),
+            (0x0001F002, 40, &[]),
+            (0x0001F003, 50, &[0]),
+            (0x0001F001, 60, &[50, 0, 55, 0]),
+        ]));
+
+        let mut loss = DataLoss::default();
+        trace.iter().for_each(|record| loss.add(&record));
+
+        assert_eq!(
+            loss.summary().as_deref(),
+            Some(
+                "200 records lost (CPU 0: 170, CPU 1: 30), idle gaps before gen:lost_records \
+                 are missing data; 1 buffer wraps (CPU 1: 1)"
+            )
+        );
+
+        let record = trace.iter().next().unwrap();
+        assert_eq!(
+            get_loss_info(&record).as_deref(),
+            Some("cpu=0 lost=120 dom=1 vcpu=0 first_tsc=5")
+        );
+        assert_eq!(DataLoss::default().summary(), None);
+    }
+}
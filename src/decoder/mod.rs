@@ -1,16 +1,37 @@
+mod guest;
 mod info;
+mod loss;
 mod name;
+mod runstate;
+mod synthetic;
+mod task;
 pub(crate) mod timestamp;
+mod vmexit;
 
-use xentrace_parser::record::{Domain, DomainKind};
+use libc::{c_int, c_long};
 
-pub(crate) use self::{info::get_record_info, name::get_record_name};
+pub(crate) use self::{
+    guest::GuestClocks,
+    info::{
+        detect_cpu_vendor, get_event_fields, get_record_extra, get_record_info, read_event_field,
+    },
+    loss::{get_loss_info, DataLoss},
+    name::{get_code_name, get_event_code, get_known_codes, get_record_name},
+    synthetic::{SyntheticRecords, SyntheticTracker},
+    task::{get_domain_pid, get_domain_task, get_idle_pid, get_pid_domain, DomainNames},
+    vmexit::ExitHistograms,
+};
+use crate::store::Record;
 
-pub(crate) fn get_record_task(domain: &Domain) -> String {
-    match domain.kind() {
-        DomainKind::Zero => format!("host/v{}", domain.virtual_cpu()),
-        DomainKind::Idle => format!("idle/v{}", domain.virtual_cpu()),
-        DomainKind::Default => "default/v?".to_string(),
-        DomainKind::Guest(dom) => format!("d{}/v{}", dom, domain.virtual_cpu()),
-    }
+/// Formats a record along with the task of its entry, named after the PID.
+pub(crate) fn get_record_dump(record: &Record, task: &str, pid: c_int, ts: c_long) -> String {
+    let event = record.event();
+
+    format!(
+        "{ts} {task}-{pid}; CPU {}; TSC {}; {}; [{}]",
+        record.cpu(),
+        event.tsc(),
+        get_record_name(event),
+        get_record_extra(event),
+    )
 }
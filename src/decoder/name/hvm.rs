@@ -89,3 +89,19 @@ pub(super) fn get_hvm_name(sub: u32, minor: u16) -> Option<&'static str> {
         _ => None,
     }
 }
+
+pub(super) fn get_hvm_events() -> impl Iterator<Item = (u32, u16, &'static str)> {
+    HVM_ENTRYEXIT_NAMES
+        .iter()
+        .map(|(minor, name)| (0x1, *minor, *name))
+        .chain(
+            HVM_HANDLER_NAMES
+                .iter()
+                .map(|(minor, name)| (0x2, *minor, *name)),
+        )
+        .chain(
+            HVM_EMUL_NAMES
+                .iter()
+                .map(|(minor, name)| (0x4, *minor, *name)),
+        )
+}
@@ -31,3 +31,14 @@ pub(super) fn get_hw_name(sub: u32, minor: u16) -> Option<&'static str> {
         _ => None,
     }
 }
+
+pub(super) fn get_hw_events() -> impl Iterator<Item = (u32, u16, &'static str)> {
+    HW_PM_NAMES
+        .iter()
+        .map(|(minor, name)| (0x1, *minor, *name))
+        .chain(
+            HW_IRQ_NAMES
+                .iter()
+                .map(|(minor, name)| (0x2, *minor, *name)),
+        )
+}
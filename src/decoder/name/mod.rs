@@ -5,10 +5,14 @@ mod sched;
 use std::collections::HashMap;
 
 use fxhash::FxBuildHasher;
-use xentrace_parser::record::Event;
+use xentrace_parser::record::EventCode;
 
-use self::{hvm::get_hvm_name, hw::get_hw_name, sched::get_sched_name};
-use crate::ffi::xen::trace::*;
+use self::{
+    hvm::{get_hvm_events, get_hvm_name},
+    hw::{get_hw_events, get_hw_name},
+    sched::{get_sched_events, get_sched_name},
+};
+use crate::{ffi::xen::trace::*, store::Event};
 
 const DOM0OP_NAMES: &[(u16, &str)] = &[
     (0x001, "dom0:create"),
@@ -16,6 +20,13 @@ const DOM0OP_NAMES: &[(u16, &str)] = &[
     // prevent fmt
 ];
 
+// Events made up by the plugin, named as the Linux ones
+const SYNTH_NAMES: &[(u16, &str)] = &[
+    (0x001, "sched/sched_switch"),
+    (0x002, "sched/sched_waking"),
+    (0x003, "hwm:exit_latency"),
+];
+
 const GEN_NAMES: &[(u16, &str)] = &[
     (0x001, "gen:lost_records"),
     (0x002, "gen:wrap_buffer"),
@@ -47,6 +58,17 @@ const PV_NAMES: &[(u16, &str)] = &[
     (0x00C, "pv:ptwr_emulation_pae"),
     (0x00D, "pv:hypercall_v2"),
     (0x00E, "pv:hypercall_subcall"),
+    // Traced by 64-bit guests
+    (0x101, "pv:hypercall64"),
+    (0x103, "pv:trap64"),
+    (0x104, "pv:page_fault64"),
+    (0x105, "pv:forced_invalid_op64"),
+    (0x106, "pv:emulate_privop64"),
+    (0x107, "pv:emulate_4gb64"),
+    (0x109, "pv:paging_fixup64"),
+    (0x10A, "pv:gdt_ldt_mapping_fault64"),
+    (0x10B, "pv:ptwr_emulation64"),
+    (0x10C, "pv:ptwr_emulation_pae64"),
 ];
 
 const SHADOW_NAMES: &[(u16, &str)] = &[
@@ -73,11 +95,67 @@ lazy_static::lazy_static! {
     static ref MEM_MAP: HashMap<u16, &'static str, FxBuildHasher> = MEM_NAMES.iter().copied().collect();
     static ref PV_MAP: HashMap<u16, &'static str, FxBuildHasher> = PV_NAMES.iter().copied().collect();
     static ref SHADOW_MAP: HashMap<u16, &'static str, FxBuildHasher> = SHADOW_NAMES.iter().copied().collect();
+    static ref SYNTH_MAP: HashMap<u16, &'static str, FxBuildHasher> = SYNTH_NAMES.iter().copied().collect();
+
+    static ref KNOWN_EVENTS: Vec<(u32, &'static str)> = get_known_events().collect();
+    static ref CODE_MAP: HashMap<&'static str, u32, FxBuildHasher> = KNOWN_EVENTS.iter().map(|(code, name)| (*name, *code)).collect();
+}
+
+const fn event_code(main: u32, sub: u32, minor: u16) -> u32 {
+    (main << 16) | (sub << 12) | minor as u32
+}
+
+fn get_known_events() -> impl Iterator<Item = (u32, &'static str)> {
+    let table = |main: u32, sub: u32, names: &'static [(u16, &'static str)]| {
+        names
+            .iter()
+            .map(move |(minor, name)| (event_code(main, sub, *minor), *name))
+    };
+
+    let pv_sub = |minor: u16| match minor {
+        0x00E => 0x2, // Hypercall subcall
+        _ => 0x1,
+    };
+
+    get_hvm_events()
+        .map(|(sub, minor, name)| (event_code(TRC_HVM, sub, minor), name))
+        .chain(get_hw_events().map(|(sub, minor, name)| (event_code(TRC_HW, sub, minor), name)))
+        .chain(
+            get_sched_events().map(|(sub, minor, name)| (event_code(TRC_SCHED, sub, minor), name)),
+        )
+        .chain(table(TRC_DOM0OP, 0x1, DOM0OP_NAMES))
+        .chain(table(TRC_GEN, 0xF, GEN_NAMES))
+        .chain(table(TRC_MEM, 0x0, MEM_NAMES))
+        .chain(
+            PV_NAMES
+                .iter()
+                .map(move |(minor, name)| (event_code(TRC_PV, pv_sub(*minor), *minor), *name)),
+        )
+        .chain(table(TRC_SHADOW, 0x0, SHADOW_NAMES))
+        .chain(table(TRC_SYNTH, 0x0, SYNTH_NAMES))
+}
+
+/// Returns the codes of all the events named by the decoder tables.
+pub(crate) fn get_known_codes() -> impl Iterator<Item = u32> {
+    KNOWN_EVENTS.iter().map(|(code, _)| *code)
+}
+
+/// Resolves an event name, as returned by `get_record_name`, back to its code.
+pub(crate) fn get_event_code(name: &str) -> Option<u32> {
+    if let Some(code) = CODE_MAP.get(name) {
+        return Some(*code);
+    }
+
+    name.strip_prefix("unknown (0x")
+        .and_then(|hex| hex.strip_suffix(')'))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
 }
 
 pub(crate) fn get_record_name(event: &Event) -> String {
-    let ecode = event.code();
+    get_code_name(event.code())
+}
 
+pub(crate) fn get_code_name(ecode: EventCode) -> String {
     let main = ecode.main();
     let sub = ecode.sub();
     let minor = ecode.minor() as u16;
@@ -90,10 +168,11 @@ pub(crate) fn get_record_name(event: &Event) -> String {
         TRC_DOM0OP => DOM0OP_MAP.get(&minor).copied(),
         TRC_GEN => GEN_MAP.get(&minor).copied(),
         TRC_MEM => MEM_MAP.get(&minor).copied(),
-        TRC_PV => PV_MAP.get(&(minor & 0x00F)).copied(),
+        TRC_PV => PV_MAP.get(&minor).copied(),
         TRC_SHADOW => SHADOW_MAP.get(&(minor & 0x00F)).copied(),
 
         TRC_GUEST => Some("TRC_GUEST"),
+        TRC_SYNTH => SYNTH_MAP.get(&minor).copied(),
         _ => None,
     };
 
@@ -102,3 +181,36 @@ pub(crate) fn get_record_name(event: &Event) -> String {
         None => format!("unknown (0x{:08X})", u32::from(ecode)),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{get_event_code, get_known_codes, get_record_name};
+    use crate::util::test::trace_records;
+
+    #[test]
+    fn name_to_code() {
+        assert_eq!(
+            get_event_code("sched:runnable_to_running"),
+            Some(0x00021101)
+        );
+        assert_eq!(get_event_code("hwm:vmexit64"), Some(0x00081102));
+        assert_eq!(get_event_code("pv:hypercall_subcall"), Some(0x0020200E));
+        assert_eq!(get_event_code("pv:page_fault"), Some(0x00201004));
+        assert_eq!(get_event_code("pv:page_fault64"), Some(0x00201104));
+        assert_eq!(get_event_code("unknown (0x0ABC0001)"), Some(0x0ABC0001));
+        assert_eq!(get_event_code("sched:nonexistent"), None);
+    }
+
+    #[test]
+    fn known_codes_round_trip() {
+        let records = get_known_codes()
+            .map(|code| (code, 0, &[] as &[u32]))
+            .collect::<Vec<_>>();
+        let trace = trace_records(&records);
+
+        for record in trace.iter() {
+            let name = get_record_name(record.event());
+            assert_eq!(get_event_code(&name), Some(record.event().code().value()));
+        }
+    }
+}
@@ -104,3 +104,19 @@ pub(super) fn get_sched_name(sub: u32, minor: u16) -> Option<&'static str> {
         _ => None,
     }
 }
+
+pub(super) fn get_sched_events() -> impl Iterator<Item = (u32, u16, &'static str)> {
+    SCHED_MIN_NAMES
+        .iter()
+        .map(|(minor, name)| (0x1, *minor, *name))
+        .chain(
+            SCHED_CLASS_NAMES
+                .iter()
+                .map(|(minor, name)| (0x2, *minor, *name)),
+        )
+        .chain(
+            SCHED_VERBOSE_NAMES
+                .iter()
+                .map(|(minor, name)| (0x8, *minor, *name)),
+        )
+}
@@ -0,0 +1,47 @@
+use crate::ffi::xen::trace::{TRC_SCHED_RUNSTATE_CHANGE, TRC_SCHED_RUNSTATE_MASK};
+
+/// Runstate of a virtual CPU, as numbered by Xen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Runstate {
+    Running = 0,
+    Runnable = 1,
+    Blocked = 2,
+    Offline = 3,
+}
+
+impl Runstate {
+    fn from_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(Self::Running),
+            1 => Some(Self::Runnable),
+            2 => Some(Self::Blocked),
+            3 => Some(Self::Offline),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the old and the new runstate of a `sched:<old>_to_<new>` event code.
+pub(crate) fn get_runstate_change(code: u32) -> Option<(Runstate, Runstate)> {
+    if code & TRC_SCHED_RUNSTATE_MASK != TRC_SCHED_RUNSTATE_CHANGE {
+        return None;
+    }
+
+    let old = Runstate::from_index(code >> 8 & 0xF)?;
+    let new = Runstate::from_index(code >> 4 & 0xF)?;
+    (old != new).then_some((old, new))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_runstate_change, Runstate::*};
+
+    #[test]
+    fn runstate_changes() {
+        assert_eq!(get_runstate_change(0x00021011), Some((Running, Runnable)));
+        assert_eq!(get_runstate_change(0x00021231), Some((Blocked, Offline)));
+        assert_eq!(get_runstate_change(0x00021002), None);
+        assert_eq!(get_runstate_change(0x00021001), None);
+        assert_eq!(get_runstate_change(0x00028011), None);
+    }
+}
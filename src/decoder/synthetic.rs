@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+};
+
+use fxhash::FxBuildHasher;
+use xentrace_parser::record::{Domain, EVENT_EXTRA_CAPACITY};
+
+use super::{
+    get_domain_pid,
+    runstate::{get_runstate_change, Runstate},
+    timestamp::TscClock,
+    vmexit::{ExitInterval, ExitTracker},
+};
+use crate::{
+    config::PidScheme,
+    ffi::xen::trace::{
+        TRC_SCHED_DOM_WAKE, TRC_SCHED_SWITCH_INFNEXT, TRC_SCHED_SWITCH_INFPREV,
+        TRC_SYNTH_EXIT_LATENCY, TRC_SYNTH_SCHED_SWITCH, TRC_SYNTH_SCHED_WAKING,
+    },
+    store::{Event, Record, TraceStore},
+};
+
+/// Linux-like scheduling events, made up from the Xen scheduler records
+/// so that the task switches and wake-ups read like the ones of a Linux trace.
+///
+/// Each one follows the record it is made from, with its CPU and TSC.
+/// A `sched/sched_switch` belongs to the previous task, a `sched/sched_waking`
+/// to the waking one. A `hwm:exit_latency` follows each VM entry with the time
+/// spent in the hypervisor since the VM exit of its virtual CPU.
+///
+/// The events are made up while the records are loaded, only what cannot be
+/// read again from their source record is kept to decode them later on.
+#[derive(Debug)]
+pub(crate) struct SyntheticRecords {
+    pid_scheme: PidScheme,
+    has_switches: bool,
+    has_wakes: bool,
+    /// Origin of the events made up by the last load, keyed by the byte offset
+    /// of their source record, in offset order.
+    origins: RwLock<Box<[(u64, Origin)]>>,
+}
+
+/// What an event is made from, besides its source record.
+#[derive(Clone, Copy, Debug)]
+enum Origin {
+    /// A switch from a virtual CPU, along with the runstate it left, if known.
+    Switch(Domain, Option<Runstate>),
+    /// A wake-up, whose virtual CPU is read from the source record.
+    Waking,
+    /// The time spent in the hypervisor, closed by the source VM entry.
+    ExitLatency(ExitInterval),
+}
+
+impl SyntheticRecords {
+    /// The switches come from the `sched:switch_infnext` records, the wake-ups
+    /// from the `sched:domain_wake` ones; traces without them fall back to the
+    /// runstate changes.
+    pub(crate) fn new(trace: &TraceStore, pid_scheme: PidScheme) -> Self {
+        Self {
+            pid_scheme,
+            has_switches: trace.contains_code(TRC_SCHED_SWITCH_INFNEXT),
+            has_wakes: trace.contains_code(TRC_SCHED_DOM_WAKE),
+            origins: RwLock::default(),
+        }
+    }
+
+    /// Starts making up the events of a load, whose records must come in TSC order.
+    pub(crate) fn tracker<'a>(&'a self, clock: &'a TscClock) -> SyntheticTracker<'a> {
+        SyntheticTracker {
+            synthetic: self,
+            clock,
+            prev: HashMap::default(),
+            prev_states: HashMap::default(),
+            pending: HashMap::default(),
+            exits: ExitTracker::default(),
+            origins: Vec::new(),
+        }
+    }
+
+    /// Returns the event made from the record at the given byte offset by the last load.
+    pub(crate) fn get(&self, offset: u64, source: &Record, clock: &TscClock) -> Option<Record> {
+        let origins = self.origins.read().unwrap_or_else(PoisonError::into_inner);
+        let index = origins
+            .binary_search_by_key(&offset, |(offset, _)| *offset)
+            .ok()?;
+
+        self.make_record(source, &origins[index].1, clock)
+    }
+
+    fn make_record(&self, source: &Record, origin: &Origin, clock: &TscClock) -> Option<Record> {
+        let (code, domain, extra) = match origin {
+            Origin::Switch(prev, prev_state) => {
+                let next = get_source_vcpu(source)?;
+                sched_switch(prev, &next, *prev_state, self.pid_scheme)
+            }
+            Origin::Waking => sched_waking(&get_source_vcpu(source)?, self.pid_scheme),
+            Origin::ExitLatency(interval) => {
+                let latency_ns = clock.duration_to_ns(interval.cycles);
+                (
+                    TRC_SYNTH_EXIT_LATENCY,
+                    None,
+                    exit_latency(interval, latency_ns),
+                )
+            }
+        };
+
+        let event = Event::new(code, source.event().tsc(), extra);
+        Some(Record::new(
+            source.cpu(),
+            domain.unwrap_or(*source.domain()),
+            event,
+        ))
+    }
+}
+
+/// Makes up the events of a load, from its records in TSC order.
+pub(crate) struct SyntheticTracker<'a> {
+    synthetic: &'a SyntheticRecords,
+    clock: &'a TscClock,
+    /// Previous virtual CPU of each CPU.
+    prev: HashMap<u32, Domain, FxBuildHasher>,
+    /// Runstate left by the last virtual CPU stopped on each CPU, as `(vcpu, runstate)`.
+    prev_states: HashMap<u32, (u32, Runstate), FxBuildHasher>,
+    /// Switches whose runstate change comes later, as `(index, vcpu)` per CPU.
+    pending: HashMap<u32, (usize, u32), FxBuildHasher>,
+    /// The exits are paired with the entries across the buffers.
+    exits: ExitTracker,
+    origins: Vec<(u64, Origin)>,
+}
+
+impl SyntheticTracker<'_> {
+    /// Adds a record, returning the event made from it, if any.
+    ///
+    /// The `prev_state` of a switch may only be known after it is returned,
+    /// the one kept for later lookups is filled in then.
+    pub(crate) fn add(&mut self, offset: u64, record: &Record) -> Option<Record> {
+        let origin = self.get_origin(record)?;
+
+        // The runstate left by the previous vCPU may follow the switch
+        if let Origin::Switch(prev, None) = origin {
+            let _ = self
+                .pending
+                .insert(record.cpu(), (self.origins.len(), u32::from(prev)));
+        }
+
+        self.origins.push((offset, origin));
+        self.synthetic.make_record(record, &origin, self.clock)
+    }
+
+    fn get_origin(&mut self, record: &Record) -> Option<Origin> {
+        if let Some(interval) = self.exits.add(record) {
+            return Some(Origin::ExitLatency(interval));
+        }
+
+        let cpu = record.cpu();
+        let code = record.event().code().value();
+        let SyntheticRecords {
+            has_switches,
+            has_wakes,
+            ..
+        } = *self.synthetic;
+
+        match (code, get_runstate_change(code)) {
+            (TRC_SCHED_SWITCH_INFPREV, _) => {
+                if let Some(vcpu) = get_verbose_vcpu(record) {
+                    let _ = self.prev.insert(cpu, vcpu);
+                }
+                None
+            }
+            (TRC_SCHED_SWITCH_INFNEXT, _) => {
+                get_verbose_vcpu(record)?;
+                Some(self.switch_origin(record))
+            }
+            (TRC_SCHED_DOM_WAKE, _) => get_verbose_vcpu(record).map(|_| Origin::Waking),
+
+            (_, Some((old, new))) => {
+                let vcpu = record.event().extra()[0].map(Domain::from)?;
+                if old == Runstate::Running {
+                    match self.pending.remove(&cpu) {
+                        Some((index, prev)) if prev == u32::from(vcpu) => {
+                            if let (_, Origin::Switch(_, prev_state)) = &mut self.origins[index] {
+                                *prev_state = Some(new);
+                            }
+                        }
+                        _ => {
+                            let _ = self.prev_states.insert(cpu, (u32::from(vcpu), new));
+                        }
+                    }
+                }
+
+                match (old, new) {
+                    (Runstate::Running, _) if !has_switches => {
+                        let _ = self.prev.insert(cpu, vcpu);
+                        None
+                    }
+                    (_, Runstate::Running) if !has_switches => Some(self.switch_origin(record)),
+                    (Runstate::Blocked | Runstate::Offline, Runstate::Runnable) if !has_wakes => {
+                        Some(Origin::Waking)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Takes the previous virtual CPU of a switch and the runstate it left, if already known.
+    fn switch_origin(&mut self, record: &Record) -> Origin {
+        let cpu = record.cpu();
+        let prev = self.prev.remove(&cpu).unwrap_or(*record.domain());
+        let prev_state = match self.prev_states.remove(&cpu) {
+            Some((vcpu, runstate)) if vcpu == u32::from(prev) => Some(runstate),
+            _ => None,
+        };
+
+        Origin::Switch(prev, prev_state)
+    }
+
+    /// Keeps the origin of the events for the lookups, until the next load.
+    pub(crate) fn finish(self) {
+        let mut origins = self.origins;
+        origins.sort_unstable_by_key(|(offset, _)| *offset);
+
+        *self
+            .synthetic
+            .origins
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = origins.into_boxed_slice();
+    }
+}
+
+type SyntheticEvent = (u32, Option<Domain>, [Option<u32>; EVENT_EXTRA_CAPACITY]);
+
+/// Reads the virtual CPU of a `sched:switch_infnext` or `sched:domain_wake` record.
+fn get_verbose_vcpu(record: &Record) -> Option<Domain> {
+    let extra = record.event().extra();
+    Some(Domain::from(extra[0]? << 16 | extra[1]?))
+}
+
+/// Reads the virtual CPU a switch or a wake-up is made for.
+fn get_source_vcpu(record: &Record) -> Option<Domain> {
+    match record.event().code().value() {
+        TRC_SCHED_SWITCH_INFNEXT | TRC_SCHED_DOM_WAKE => get_verbose_vcpu(record),
+        _ => record.event().extra()[0].map(Domain::from),
+    }
+}
+
+fn sched_switch(
+    prev: &Domain,
+    next: &Domain,
+    prev_state: Option<Runstate>,
+    pid_scheme: PidScheme,
+) -> SyntheticEvent {
+    let mut extra = [None; EVENT_EXTRA_CAPACITY];
+    extra[..7].copy_from_slice(&[
+        Some(get_domain_pid(prev, pid_scheme) as u32),
+        Some(get_domain_pid(next, pid_scheme) as u32),
+        Some(u16::from(prev.kind()).into()),
+        Some(prev.virtual_cpu().into()),
+        Some(u16::from(next.kind()).into()),
+        Some(next.virtual_cpu().into()),
+        prev_state.map(get_task_state),
+    ]);
+
+    (TRC_SYNTH_SCHED_SWITCH, Some(*prev), extra)
+}
+
+/// Linux state of a task leaving the CPU: `TASK_RUNNING` if preempted,
+/// `TASK_INTERRUPTIBLE` if blocked and `TASK_UNINTERRUPTIBLE` if offline.
+fn get_task_state(runstate: Runstate) -> u32 {
+    match runstate {
+        Runstate::Running | Runstate::Runnable => 0,
+        Runstate::Blocked => 1,
+        Runstate::Offline => 2,
+    }
+}
+
+fn sched_waking(vcpu: &Domain, pid_scheme: PidScheme) -> SyntheticEvent {
+    let mut extra = [None; EVENT_EXTRA_CAPACITY];
+    extra[..3].copy_from_slice(&[
+        Some(get_domain_pid(vcpu, pid_scheme) as u32),
+        Some(u16::from(vcpu.kind()).into()),
+        Some(vcpu.virtual_cpu().into()),
+    ]);
+
+    (TRC_SYNTH_SCHED_WAKING, None, extra)
+}
+
+fn exit_latency(interval: &ExitInterval, latency_ns: u64) -> [Option<u32>; EVENT_EXTRA_CAPACITY] {
+    let mut extra = [None; EVENT_EXTRA_CAPACITY];
+    extra[..5].copy_from_slice(&[
+        Some(interval.exit_reason),
+        Some(latency_ns as u32),
+        Some((latency_ns >> 32) as u32),
+        Some(interval.handlers),
+        interval.last_handler,
+    ]);
+
+    extra
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        SyntheticRecords, TRC_SYNTH_EXIT_LATENCY, TRC_SYNTH_SCHED_SWITCH, TRC_SYNTH_SCHED_WAKING,
+    };
+    use crate::{
+        config::PidScheme,
+        decoder::{get_record_info, get_record_name, timestamp::TscClock},
+        store::{Record, TraceStore},
+        util::test::trace_bytes,
+    };
+
+    /// Makes up the events of a trace as a load does,
+    /// then looks them up by the offset of their source record.
+    fn make_up(records: &[(u32, u64, &[u32])], pid_scheme: PidScheme) -> Vec<(u64, Record)> {
+        let trace = TraceStore::from_bytes(&trace_bytes(records));
+        let clock = TscClock::new(&trace, 2_400_000_000.0);
+        let synthetic = SyntheticRecords::new(&trace, pid_scheme);
+
+        let mut tracker = synthetic.tracker(&clock);
+        let offsets = trace
+            .iter_sorted()
+            .filter_map(|(offset, record)| tracker.add(offset, &record).map(|_| offset))
+            .collect::<Vec<_>>();
+        tracker.finish();
+
+        offsets
+            .into_iter()
+            .map(|offset| {
+                let source = trace.get(offset).unwrap();
+                (offset, synthetic.get(offset, &source, &clock).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn verbose_sched_events() {
+        let synthetic = make_up(
+            &[
+                (0x00021002, 10, &[0x0001_0000]), // d1/v0 runs
+                (0x00028004, 20, &[2, 1]),        // d2/v1 wakes
+                (0x0002800E, 30, &[1, 0, 100]),   // d1/v0 leaves
+                (0x0002800F, 30, &[2, 1, 5, 10]), // d2/v1 comes
+                (0x00021021, 30, &[0x0001_0000]), // d1/v0 running -> blocked
+                (0x00021211, 40, &[0x0003_0000]), // d3/v0 blocked -> runnable
+            ],
+            PidScheme::Vcpu,
+        );
+        assert_eq!(synthetic.len(), 2);
+
+        let (offset, waking) = &synthetic[0];
+        assert_eq!(*offset, 16);
+        assert_eq!(waking.event().code(), TRC_SYNTH_SCHED_WAKING);
+        assert_eq!(get_record_name(waking.event()), "sched/sched_waking");
+        assert_eq!(
+            get_record_info(waking.event(), None),
+            "pid=65537 dom=2 vcpu=1"
+        );
+
+        // The runstate left by d1/v0 comes after the switch
+        let (offset, switch) = &synthetic[1];
+        assert_eq!(*offset, 60);
+        assert_eq!(switch.event().code(), TRC_SYNTH_SCHED_SWITCH);
+        assert_eq!(switch.event().tsc(), 30);
+        assert_eq!(
+            get_record_info(switch.event(), None),
+            "prev_pid=32768 prev_state=S next_pid=65537 prev_dom=1 prev_vcpu=0 next_dom=2 next_vcpu=1"
+        );
+    }
+
+    #[test]
+    fn runstate_sched_events() {
+        let synthetic = make_up(
+            &[
+                (0x00021011, 10, &[0x0001_0000]), // d1/v0 running -> runnable
+                (0x00021101, 10, &[0x0002_0001]), // d2/v1 runnable -> running
+                (0x00021211, 20, &[0x0003_0000]), // d3/v0 blocked -> runnable
+            ],
+            PidScheme::Domain,
+        );
+        assert_eq!(synthetic.len(), 2);
+
+        assert_eq!(
+            get_record_info(synthetic[0].1.event(), None),
+            "prev_pid=1 prev_state=R next_pid=2 prev_dom=1 prev_vcpu=0 next_dom=2 next_vcpu=1"
+        );
+        assert_eq!(
+            get_record_info(synthetic[1].1.event(), None),
+            "pid=3 dom=3 vcpu=0"
+        );
+    }
+
+    #[test]
+    fn exit_latency_events() {
+        let synthetic = make_up(
+            &[
+                (0x00021002, 0, &[0x0001_0000]),      // d1/v0 runs
+                (0x00081102, 100, &[0x1E, 0, 0]),     // VM exit
+                (0x00082016, 120, &[0x20, 0x1, 0x2]), // Handler
+                (0x00081001, 2_500, &[]),             // VM entry
+            ],
+            PidScheme::Vcpu,
+        );
+        assert_eq!(synthetic.len(), 1);
+
+        let (offset, latency) = &synthetic[0];
+        assert_eq!(*offset, 64);
+        assert_eq!(latency.event().code(), TRC_SYNTH_EXIT_LATENCY);
+        assert_eq!(latency.event().tsc(), 2_500);
+        assert_eq!(get_record_name(latency.event()), "hwm:exit_latency");
+        assert_eq!(
+            get_record_info(latency.event(), None),
+            "exit_reason=0x1E latency=1.000us handlers=1 handler=hwm:ioport_read"
+        );
+    }
+}
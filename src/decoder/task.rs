@@ -0,0 +1,203 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use fxhash::FxBuildHasher;
+use libc::c_int;
+use xentrace_parser::record::{Domain, DomainKind};
+
+use crate::{
+    config::{Config, PidScheme},
+    log,
+};
+
+const XL_LIST_EXTENSION: &str = "xl-list";
+
+/// Names of the domains, used in place of the `d<N>` notation.
+#[derive(Debug, Default)]
+pub(crate) struct DomainNames(HashMap<u16, String, FxBuildHasher>);
+
+impl DomainNames {
+    /// Collects the names from the `xl list` dump saved as `<trace>.xl-list`,
+    /// then from the configuration file, which takes precedence.
+    ///
+    /// The `dom0:create` records only carry the ID of the new domain,
+    /// so they cannot contribute any name.
+    pub(crate) fn load(trace_path: &Path, config: &Config) -> Self {
+        let mut names = HashMap::default();
+
+        let mut xl_list_path = trace_path.as_os_str().to_owned();
+        xl_list_path.push(".");
+        xl_list_path.push(XL_LIST_EXTENSION);
+
+        if let Ok(content) = fs::read_to_string(&xl_list_path) {
+            names.extend(parse_xl_list(&content));
+            log!("Domain names loaded from {xl_list_path:?}");
+        }
+
+        names.extend(
+            config
+                .domains
+                .iter()
+                .map(|(dom_id, name)| (*dom_id, name.clone())),
+        );
+
+        Self(names)
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, dom_id: u16) -> Option<&str> {
+        self.0.get(&dom_id).map(String::as_str)
+    }
+}
+
+/// Parses the output of `xl list`, whose rows start with the name and the ID of a domain.
+fn parse_xl_list(content: &str) -> impl Iterator<Item = (u16, String)> + '_ {
+    content.lines().filter_map(|line| {
+        let mut columns = line.split_whitespace();
+        let name = columns.next()?;
+        let dom_id = columns.next()?.parse().ok()?; // Skips the header
+        Some((dom_id, name.to_string()))
+    })
+}
+
+/// Number of low PID bits holding the virtual CPU, in the `PidScheme::Vcpu` scheme.
+const PID_VCPU_BITS: u32 = 15;
+const PID_VCPU_MASK: c_int = (1 << PID_VCPU_BITS) - 1;
+
+/// Builds the PID of a domain, which keeps the real domain ID so that it
+/// matches the task name and the `xenalyze` output:
+///
+/// | Domain                  | `PidScheme::Vcpu`      | `PidScheme::Domain` |
+/// |-------------------------|------------------------|---------------------|
+/// | `DomainKind::Zero`      | `vcpu`                 | `0`                 |
+/// | `DomainKind::Guest(N)`  | `N << 15 \| vcpu`      | `N`                 |
+/// | `DomainKind::Idle`      | `32767 << 15`          | `32767`             |
+/// | `DomainKind::Default`   | `32768 << 15 \| vcpu`  | `32768`             |
+///
+/// All the virtual CPUs of the idle domain share the same PID, which is the
+/// `DataStream::idle_pid` returned by `get_idle_pid`.
+pub(crate) fn get_domain_pid(domain: &Domain, scheme: PidScheme) -> c_int {
+    let dom_id = u16::from(domain.kind()) as c_int;
+
+    match (scheme, domain.kind()) {
+        (PidScheme::Vcpu, DomainKind::Idle) => dom_id << PID_VCPU_BITS,
+        (PidScheme::Vcpu, _) => {
+            (dom_id << PID_VCPU_BITS) | (domain.virtual_cpu() as c_int & PID_VCPU_MASK)
+        }
+        (PidScheme::Domain, _) => dom_id,
+    }
+}
+
+/// PID of the idle domain, to be set as `DataStream::idle_pid`.
+pub(crate) fn get_idle_pid(scheme: PidScheme) -> c_int {
+    get_domain_pid(
+        &Domain::from((u16::from(DomainKind::Idle) as u32) << 16),
+        scheme,
+    )
+}
+
+/// Decodes a PID built by `get_domain_pid`, returning the domain ID
+/// and, if the scheme keeps it, the virtual CPU.
+pub(crate) fn get_pid_domain(pid: c_int, scheme: PidScheme) -> Option<(u16, Option<u16>)> {
+    match scheme {
+        PidScheme::Vcpu => {
+            let dom_id = u16::try_from(pid >> PID_VCPU_BITS).ok()?;
+            let vcpu = (pid & PID_VCPU_MASK) as u16;
+            Some((dom_id, Some(vcpu)))
+        }
+        PidScheme::Domain => Some((u16::try_from(pid).ok()?, None)),
+    }
+}
+
+/// Builds the task name of a domain, with the virtual CPU if known.
+pub(crate) fn get_domain_task(dom_id: u16, vcpu: Option<u16>, names: &DomainNames) -> String {
+    let kind = DomainKind::from(dom_id);
+    let name = match (names.get(dom_id), kind) {
+        (Some(name), _) => name.to_string(),
+        (None, DomainKind::Idle) => return "idle".to_string(),
+        (None, DomainKind::Zero) => "host".to_string(),
+        (None, DomainKind::Default) => "default".to_string(),
+        (None, DomainKind::Guest(dom)) => format!("d{dom}"),
+    };
+
+    match (kind, vcpu) {
+        (DomainKind::Idle, _) | (_, None) => name,
+        (_, Some(vcpu)) => format!("{name}/v{vcpu}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use xentrace_parser::record::Domain;
+
+    use super::{
+        get_domain_pid, get_domain_task, get_idle_pid, get_pid_domain, parse_xl_list, DomainNames,
+    };
+    use crate::config::PidScheme;
+
+    fn get_record_task(domain: &Domain, names: &DomainNames) -> String {
+        get_domain_task(u16::from(domain.kind()), Some(domain.virtual_cpu()), names)
+    }
+
+    #[test]
+    fn pid_round_trip() {
+        let names = DomainNames::default();
+
+        for (value, vcpu_pid, domain_pid, task) in [
+            (0x0000_0000, 0x0000_0000, 0, "host/v0"),
+            (0x0000_0003, 0x0000_0003, 0, "host/v3"),
+            (0x0001_0000, 0x0000_8000, 1, "d1/v0"),
+            (0x0003_0002, 0x0001_8002, 3, "d3/v2"),
+            (0x7FFE_0005, 0x3FFF_0005, 32766, "d32766/v5"),
+            (0x8000_0001, 0x4000_0001, 32768, "default/v1"),
+        ] {
+            let domain = Domain::from(value);
+            let dom_id = u16::from(domain.kind());
+
+            assert_eq!(get_domain_pid(&domain, PidScheme::Vcpu), vcpu_pid);
+            assert_eq!(
+                get_pid_domain(vcpu_pid, PidScheme::Vcpu),
+                Some((dom_id, Some(domain.virtual_cpu())))
+            );
+            assert_eq!(get_record_task(&domain, &names), task);
+
+            assert_eq!(get_domain_pid(&domain, PidScheme::Domain), domain_pid);
+            assert_eq!(
+                get_pid_domain(domain_pid, PidScheme::Domain),
+                Some((dom_id, None))
+            );
+        }
+
+        for scheme in [PidScheme::Vcpu, PidScheme::Domain] {
+            let idle_pid = get_idle_pid(scheme);
+            assert_eq!(get_domain_pid(&Domain::from(0x7FFF_0003), scheme), idle_pid);
+
+            let (dom_id, vcpu) = get_pid_domain(idle_pid, scheme).unwrap();
+            assert_eq!(get_domain_task(dom_id, vcpu, &names), "idle");
+        }
+
+        assert_eq!(get_domain_task(3, None, &names), "d3");
+        assert_eq!(get_pid_domain(-1, PidScheme::Vcpu), None);
+    }
+
+    #[test]
+    fn xl_list_names() {
+        let names = DomainNames(
+            parse_xl_list(
+                "Name                                        ID   Mem VCPUs\tState\tTime(s)\n\
+                 Domain-0                                     0  4096     4     r-----     123.4\n\
+                 webserver-01                                 3  2048     2     -b----      12.3\n",
+            )
+            .collect(),
+        );
+
+        assert_eq!(names.get(0), Some("Domain-0"));
+        assert_eq!(names.get(3), Some("webserver-01"));
+        assert_eq!(names.get(4), None);
+
+        assert_eq!(
+            get_record_task(&Domain::from(0x0003_0001), &names),
+            "webserver-01/v1"
+        );
+        assert_eq!(get_record_task(&Domain::from(0x0004_0000), &names), "d4/v0");
+    }
+}
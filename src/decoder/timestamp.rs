@@ -1,7 +1,10 @@
-use std::env;
+use std::{collections::HashMap, env};
 
+use fxhash::FxBuildHasher;
 use libc::{c_double, c_long, c_ulong};
 
+use crate::{config::Config, ffi::xen::trace::TRC_PM_FREQ_CHANGE, log, store::TraceStore};
+
 const ENV_VAR_CPU_HZ: &str = "XENTRACE_CPUHZ";
 const DEFAULT_CPU_HZ: c_double = 2_400_000_000.0;
 
@@ -9,38 +12,196 @@ const GHZ: c_double = 1_000_000_000.0;
 const MHZ: c_double = 1_000_000.0;
 const KHZ: c_double = 1_000.0;
 
-pub(crate) fn get_env_cpu_freq() -> c_double {
-    let cpu_freq = env::var(ENV_VAR_CPU_HZ)
+/// Parses a frequency in the `XENTRACE_CPUHZ` format (eg. `3.6G`, `2400M`, `2400000000`).
+pub(crate) fn parse_cpu_freq(freq: &str) -> Option<c_double> {
+    let mut freq = freq.trim().to_string();
+
+    let multiplier = match freq.chars().last()? {
+        'G' => {
+            let _ = freq.pop();
+            GHZ
+        }
+        'M' => {
+            let _ = freq.pop();
+            MHZ
+        }
+        'K' => {
+            let _ = freq.pop();
+            KHZ
+        }
+        '0'..='9' => 1.0,
+        _ => return None,
+    };
+
+    freq.parse::<c_double>()
+        .map(|base_hz| base_hz * multiplier)
+        .ok()
+        .filter(|hz| *hz > 0.0)
+}
+
+/// Guesses the frequency from the first `hw/pm:freq_change` record, as the one
+/// the CPU was running at when the trace started.
+///
+/// This is a heuristic: the TSC ticks at the nominal frequency, which the CPU
+/// leaves for turbo or power saving states, so the guess may be off.
+fn get_trace_cpu_freq(trace: &TraceStore) -> Option<c_double> {
+    if !trace.contains_code(TRC_PM_FREQ_CHANGE) {
+        return None;
+    }
+
+    let record = trace
+        .iter()
+        .find(|record| record.event().code() == TRC_PM_FREQ_CHANGE)?;
+    let [old_mhz, new_mhz, ..] = *record.event().extra();
+
+    old_mhz
+        .or(new_mhz)
+        .filter(|mhz| *mhz > 0)
+        .map(|mhz| mhz as c_double * MHZ)
+}
+
+fn get_env_cpu_freq() -> Option<c_double> {
+    env::var(ENV_VAR_CPU_HZ)
         .ok()
-        .and_then(|mut env_freq| {
-            let multiplier = match env_freq.chars().last()? {
-                'G' => {
-                    let _ = env_freq.pop();
-                    GHZ
-                }
-                'M' => {
-                    let _ = env_freq.pop();
-                    MHZ
-                }
-                'K' => {
-                    let _ = env_freq.pop();
-                    KHZ
-                }
-                '0'..='9' => 1.0,
-                _ => return None,
-            };
-
-            env_freq
-                .parse::<c_double>()
-                .map(|base_hz| base_hz * multiplier)
-                .ok()
-        })
-        .unwrap_or(DEFAULT_CPU_HZ);
-
-    cpu_freq * 1024.0 / GHZ
-}
-
-pub(crate) fn tsc_to_ns(tsc: c_ulong, cpu_qhz: c_double, first_tsc: Option<c_ulong>) -> c_long {
-    let tsc = first_tsc.map(|first| (tsc - first) << 10).unwrap_or(tsc);
-    (tsc as c_double / cpu_qhz) as c_long
+        .and_then(|env_freq| parse_cpu_freq(&env_freq))
+}
+
+/// Detects the TSC frequency (in Hz) of the host the trace was captured on.
+pub(crate) fn detect_cpu_freq(trace: &TraceStore, config: &Config) -> c_double {
+    let config_freq = config.cpu_hz.as_deref().and_then(parse_cpu_freq);
+
+    let (cpu_hz, source) = config_freq
+        .map(|hz| (hz, "configuration file"))
+        .or_else(|| get_env_cpu_freq().map(|hz| (hz, ENV_VAR_CPU_HZ)))
+        .or_else(|| get_trace_cpu_freq(trace).map(|hz| (hz, "first hw/pm:freq_change record")))
+        .unwrap_or((DEFAULT_CPU_HZ, "default value"));
+
+    log!("CPU frequency: {cpu_hz} Hz (from {source})");
+    cpu_hz
+}
+
+/// Converts the TSC of the records into nanoseconds from the start of the trace.
+///
+/// The TSC of each CPU is measured from its first record, so records preceding
+/// it (eg. on hosts with unsynchronized TSC) get a negative delta rather than
+/// wrapping, and the conversion is done in 128-bit to not overflow on long traces.
+#[derive(Debug)]
+pub(crate) struct TscClock {
+    /// TSC frequency (in Hz), never zero.
+    hz: u64,
+    /// First TSC of each CPU and its time from the start of the trace.
+    bases: HashMap<u32, (u64, i64), FxBuildHasher>,
+    first_tsc: u64,
+}
+
+impl TscClock {
+    pub(crate) fn new(trace: &TraceStore, cpu_hz: c_double) -> Self {
+        let hz = (cpu_hz as u64).max(1);
+        let first_tsc = trace.first_tsc().unwrap_or_default();
+        let bases = (0..trace.cpu_count())
+            .filter_map(|cpu| {
+                let base = trace.cpu_first_tsc(cpu)?;
+                Some((cpu, (base, cycles_to_ns(delta(base, first_tsc), hz))))
+            })
+            .collect();
+
+        let regressions = trace.tsc_regressions();
+        if !regressions.is_empty() {
+            let total = regressions.values().sum::<usize>();
+            let per_cpu = regressions
+                .iter()
+                .map(|(cpu, count)| format!("CPU {cpu}: {count}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            log!("Warning: {total} records out of TSC order ({per_cpu}), sorted by TSC");
+        }
+
+        Self {
+            hz,
+            bases,
+            first_tsc,
+        }
+    }
+
+    /// Converts the TSC of a record on the given CPU.
+    pub(crate) fn to_ns(&self, cpu: u32, tsc: c_ulong) -> c_long {
+        let (base, base_ns) = self.bases.get(&cpu).copied().unwrap_or((self.first_tsc, 0));
+
+        base_ns.saturating_add(cycles_to_ns(delta(tsc, base), self.hz)) as c_long
+    }
+
+    /// Converts a number of TSC cycles.
+    pub(crate) fn duration_to_ns(&self, cycles: u64) -> u64 {
+        cycles_to_ns(cycles.into(), self.hz) as u64
+    }
+
+    /// Returns the time (in nanoseconds) when the TSC of a guest, that is
+    /// `scale * tsc + offset`, reads zero.
+    pub(crate) fn get_zero_ns(&self, offset: f64, scale: f64) -> i64 {
+        let tsc = -offset / scale - self.first_tsc as f64;
+        (tsc * 1_000_000_000.0 / self.hz as f64) as i64
+    }
+}
+
+#[inline]
+fn delta(tsc: u64, base: u64) -> i128 {
+    i128::from(tsc) - i128::from(base)
+}
+
+fn cycles_to_ns(cycles: i128, hz: u64) -> i64 {
+    let ns = cycles * 1_000_000_000 / i128::from(hz);
+    ns.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_trace_cpu_freq, parse_cpu_freq, TscClock};
+    use crate::{store::TraceStore, util::test::trace_bytes};
+
+    #[test]
+    fn parse_frequencies() {
+        assert_eq!(parse_cpu_freq("3.6G"), Some(3_600_000_000.0));
+        assert_eq!(parse_cpu_freq("2400M\n"), Some(2_400_000_000.0));
+        assert_eq!(parse_cpu_freq("1000"), Some(1_000.0));
+        assert_eq!(parse_cpu_freq("fast"), None);
+        assert_eq!(parse_cpu_freq("0G"), None);
+    }
+
+    #[test]
+    fn trace_frequency() {
+        let trace = TraceStore::from_bytes(&trace_bytes(&[
+            (0x00801001, 10, &[2_400, 1_200]),
+            (0x00801001, 20, &[1_200, 3_000]),
+        ]));
+
+        assert_eq!(get_trace_cpu_freq(&trace), Some(2_400_000_000.0));
+    }
+
+    #[test]
+    fn unsynchronized_tsc() {
+        let trace = TraceStore::from_bytes(&trace_bytes(&[
+            (0x00801001, 2_000, &[]),
+            (0x00801001, 1_500, &[]), // Out of order
+            (0x0001F003, 0, &[1]),
+            (0x00801001, 1_000, &[]), // Behind CPU 0
+            (0x00801001, u64::MAX, &[]),
+        ]));
+        let clock = TscClock::new(&trace, 1_000_000_000.0);
+
+        assert_eq!(trace.tsc_regressions().get(&0), Some(&1));
+        assert_eq!(clock.to_ns(0, 2_000), 1_000);
+        assert_eq!(clock.to_ns(0, 1_500), 500);
+        assert_eq!(clock.to_ns(1, 1_000), 0);
+        assert_eq!(clock.to_ns(1, 500), -500);
+        assert_eq!(clock.to_ns(1, u64::MAX), i64::MAX);
+
+        // 100 years at 3.6 GHz
+        let clock = TscClock::new(&trace, 3_600_000_000.0);
+        let tsc = 1_000 + 3_600_000_000 * 86_400 * 36_500;
+        assert_eq!(clock.to_ns(1, tsc), 1_000_000_000 * 86_400 * 36_500);
+
+        // A guest TSC twice as fast and 3.6G cycles ahead
+        assert_eq!(clock.get_zero_ns(3_600_000_000.0, 2.0), -500_000_000 - 277);
+    }
 }
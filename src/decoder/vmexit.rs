@@ -0,0 +1,214 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use fxhash::FxBuildHasher;
+
+use super::info::{write_exit_reason, write_nanos};
+use crate::{
+    config::CpuVendor,
+    ffi::xen::trace::{
+        TRC_HVM_HANDLER, TRC_HVM_HANDLER_MASK, TRC_HVM_VMENTRY, TRC_HVM_VMEXIT, TRC_HVM_VMEXIT64,
+        TRC_SYNTH_EXIT_LATENCY,
+    },
+    log,
+    store::Record,
+};
+
+/// Number of histogram buckets, the last one holding the exits of 2^31ns or more.
+const HISTOGRAM_BUCKETS: usize = 32;
+
+/// Time spent by a virtual CPU in the hypervisor, from a VM exit to the next VM entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ExitInterval {
+    pub(crate) exit_reason: u32,
+    pub(crate) cycles: u64,
+    /// Number of handler records (`hwm:*` of the handler class) between exit and entry.
+    pub(crate) handlers: u32,
+    /// Code of the last handler record, if any.
+    pub(crate) last_handler: Option<u32>,
+}
+
+/// Pairs each VM exit with the following VM entry of the same virtual CPU.
+/// The records of each CPU are expected in order.
+#[derive(Debug, Default)]
+pub(crate) struct ExitTracker {
+    /// Pending exit of each virtual CPU.
+    exits: HashMap<(u16, u16), (u64, ExitInterval), FxBuildHasher>,
+}
+
+impl ExitTracker {
+    /// Adds a record, returning the interval that a VM entry closes.
+    pub(crate) fn add(&mut self, record: &Record) -> Option<ExitInterval> {
+        let event = record.event();
+        let vcpu = (
+            u16::from(record.domain().kind()),
+            record.domain().virtual_cpu(),
+        );
+
+        match event.code().value() {
+            TRC_HVM_VMEXIT | TRC_HVM_VMEXIT64 => {
+                let interval = ExitInterval {
+                    exit_reason: event.extra()[0].unwrap_or_default(),
+                    cycles: 0,
+                    handlers: 0,
+                    last_handler: None,
+                };
+
+                let _ = self.exits.insert(vcpu, (event.tsc(), interval));
+                None
+            }
+            TRC_HVM_VMENTRY => {
+                let (exit_tsc, mut interval) = self.exits.remove(&vcpu)?;
+                interval.cycles = event.tsc().saturating_sub(exit_tsc);
+                Some(interval)
+            }
+            code if code & TRC_HVM_HANDLER_MASK == TRC_HVM_HANDLER => {
+                if let Some((_, interval)) = self.exits.get_mut(&vcpu) {
+                    interval.handlers += 1;
+                    interval.last_handler = Some(code);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Latency histogram of the VM exits with the same reason.
+#[derive(Clone, Debug)]
+struct Histogram {
+    count: u64,
+    total_ns: u64,
+    max_ns: u64,
+    /// Number of exits in `[2^(i-1), 2^i)` nanoseconds.
+    buckets: [u64; HISTOGRAM_BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            total_ns: 0,
+            max_ns: 0,
+            buckets: [0; HISTOGRAM_BUCKETS],
+        }
+    }
+}
+
+/// Latency histograms of the VM exits, keyed by exit reason.
+#[derive(Debug, Default)]
+pub(crate) struct ExitHistograms {
+    reasons: HashMap<u32, Histogram, FxBuildHasher>,
+}
+
+impl ExitHistograms {
+    /// Adds a record, counting the `hwm:exit_latency` ones.
+    pub(crate) fn add(&mut self, record: &Record) {
+        let event = record.event();
+        if event.code() != TRC_SYNTH_EXIT_LATENCY {
+            return;
+        }
+
+        if let [Some(exit_reason), Some(low), Some(high), ..] = *event.extra() {
+            self.add_latency(exit_reason, u64::from(low) | u64::from(high) << 32);
+        }
+    }
+
+    fn add_latency(&mut self, exit_reason: u32, latency_ns: u64) {
+        let histogram = self.reasons.entry(exit_reason).or_default();
+        let bucket = (u64::BITS - latency_ns.leading_zeros()) as usize;
+
+        histogram.count += 1;
+        histogram.total_ns = histogram.total_ns.saturating_add(latency_ns);
+        histogram.max_ns = histogram.max_ns.max(latency_ns);
+        histogram.buckets[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+
+    /// Returns one line per exit reason, the most time consuming first.
+    pub(crate) fn summary(&self, vendor: Option<CpuVendor>) -> Vec<String> {
+        let mut reasons = self.reasons.iter().collect::<Vec<_>>();
+        reasons.sort_by_key(|(reason, histogram)| (u64::MAX - histogram.total_ns, **reason));
+
+        reasons
+            .into_iter()
+            .map(|(reason, histogram)| {
+                let mut line = String::new();
+                write_exit_reason(&mut line, *reason, vendor);
+                let _ = write!(line, ": {} exits, mean ", histogram.count);
+                write_nanos(&mut line, histogram.total_ns / histogram.count);
+                line.push_str(", max ");
+                write_nanos(&mut line, histogram.max_ns);
+                line.push_str(", histogram");
+
+                for (bucket, count) in histogram.buckets.iter().enumerate() {
+                    if *count > 0 {
+                        line.push_str(" <");
+                        write_nanos(&mut line, 1 << bucket);
+                        let _ = write!(line, ":{count}");
+                    }
+                }
+
+                line
+            })
+            .collect()
+    }
+
+    pub(crate) fn report(&self, vendor: Option<CpuVendor>) {
+        if !self.reasons.is_empty() {
+            log!("VM exit latencies:");
+            self.summary(vendor)
+                .iter()
+                .for_each(|line| log!("  {line}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExitHistograms, ExitInterval, ExitTracker};
+    use crate::{config::CpuVendor, util::test::trace_records};
+
+    #[test]
+    fn exit_intervals() {
+        let trace = trace_records(&[
+            (0x00021002, 0, &[0x0001_0000]),    // d1/v0 runs
+            (0x00081002, 100, &[0x30, 0x1000]), // VM exit
+            (0x00082014, 120, &[0x10]),         // Handler
+            (0x00082016, 150, &[0x20]),         // Handler
+            (0x00081001, 400, &[]),             // VM entry
+            (0x00081001, 500, &[]),             // VM entry without exit
+        ]);
+
+        let mut tracker = ExitTracker::default();
+        let intervals = trace
+            .iter()
+            .filter_map(|record| tracker.add(record))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            intervals,
+            &[ExitInterval {
+                exit_reason: 0x30,
+                cycles: 300,
+                handlers: 2,
+                last_handler: Some(0x00082016),
+            }]
+        );
+    }
+
+    #[test]
+    fn exit_histograms() {
+        let mut histograms = ExitHistograms::default();
+        histograms.add_latency(0x1, 1_500);
+        histograms.add_latency(0x30, 900);
+        histograms.add_latency(0x30, 1_100);
+        histograms.add_latency(0x30, 3_000);
+
+        assert_eq!(
+            histograms.summary(Some(CpuVendor::Intel)),
+            &[
+                "EPT_VIOLATION: 3 exits, mean 1.666us, max 3.000us, histogram <1.024us:1 <2.048us:1 <4.096us:1",
+                "EXTERNAL_INTERRUPT: 1 exits, mean 1.500us, max 1.500us, histogram <2.048us:1",
+            ]
+        );
+    }
+}
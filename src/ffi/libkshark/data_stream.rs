@@ -1,4 +1,4 @@
-use std::ptr::null_mut;
+use std::{ptr::null_mut, slice};
 
 use libc::{c_char, c_int, c_long, c_short, c_void, size_t};
 
@@ -14,6 +14,9 @@ extern "C" {
     ) -> c_int;
 }
 
+/// Function calibrating a timestamp with the array of time calibration constants.
+pub type TimeCalibFunc = extern "C" fn(ts: *mut c_long, argv: *mut c_long);
+
 /// Structure representing a stream of trace data.
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -58,7 +61,7 @@ pub struct DataStream /* DataStream */ {
     /// The number of plugins registered for this stream.
     pub n_plugins: c_int,
     /// System clock calibration function.
-    pub calib: *mut c_void, // time_calib_func - NOT IMPL!
+    pub calib: Option<TimeCalibFunc>,
     /// An array of time calibration constants.
     pub calib_array: *mut c_long,
     /// The size of the array of time calibration constants.
@@ -96,6 +99,23 @@ impl DataStream {
         str_from_ptr!(self.file)
     }
 
+    /// Returns the time calibration constants of the stream.
+    pub fn get_calib_array(&self) -> &[c_long] {
+        match self.calib_array.is_null() {
+            true => &[],
+            false => unsafe { slice::from_raw_parts(self.calib_array, self.calib_array_size) },
+        }
+    }
+
+    /// Applies the time calibration function of the stream, if any.
+    pub fn calibrate(&self, mut ts: c_long) -> c_long {
+        if let Some(calib) = self.calib.filter(|_| !self.calib_array.is_null()) {
+            calib(&mut ts, self.calib_array);
+        }
+
+        ts
+    }
+
     #[inline]
     pub fn get_interface<'a>(&'a self) -> Option<&'a GenericStreamInterface> {
         unsafe { self.interface.as_ref::<'a>() }
@@ -129,7 +149,7 @@ impl Default for DataStream {
             data_format: Default::default(),
             plugins: null_mut(),
             n_plugins: Default::default(),
-            calib: null_mut(),
+            calib: None,
             calib_array: null_mut(),
             calib_array_size: Default::default(),
             event_handlers: null_mut(),
@@ -142,11 +162,18 @@ impl Default for DataStream {
 #[cfg(test)]
 mod test {
     use super::DataStream;
+    use libc::{c_int, c_void};
     use std::{
         mem::{align_of, size_of, MaybeUninit},
         ptr::addr_of,
     };
 
+    // Stands in for libkshark, which the tests are not linked to.
+    #[no_mangle]
+    extern "C" fn kshark_hash_id_add(_: *mut c_void, _: c_int) -> c_int {
+        0
+    }
+
     #[test]
     fn bindgen_layout() {
         const UNINIT: MaybeUninit<DataStream> = MaybeUninit::uninit();
@@ -12,6 +12,19 @@ pub enum DataInterfaceId /* kshark_data_interface_id */ {
     GenericDataInterface = 1,
 }
 
+/// Event field format identifier.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub enum EventFieldFormat /* kshark_event_field_format */ {
+    /// A field of unknown type.
+    Invalid = 0,
+    /// Integer number.
+    Integer = 1,
+    /// Floating-point number.
+    Float = 2,
+}
+
 /// Structure representing the interface of methods used to
 /// operate over the data from a given stream.
 #[repr(C)]
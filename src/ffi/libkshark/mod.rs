@@ -6,9 +6,9 @@ mod generic_stream_interface;
 // Structs & Enums
 #[allow(unused_imports)]
 pub use self::{
-    data_stream::DataStream,
+    data_stream::{DataStream, TimeCalibFunc},
     entry::Entry,
-    generic_stream_interface::{DataInterfaceId, GenericStreamInterface},
+    generic_stream_interface::{DataInterfaceId, EventFieldFormat, GenericStreamInterface},
 };
 
 // Constants
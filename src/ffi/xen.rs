@@ -12,4 +12,32 @@ pub mod trace {
     pub const TRC_SHADOW: c_uint = 0x0040; // Xen shadow tracing
     pub const TRC_HW: c_uint = 0x0080; // Xen hardware-related traces
     pub const TRC_GUEST: c_uint = 0x0800; // Guest-generated traces
+
+    // Class of the events made up by the plugin, unused by Xen
+    pub const TRC_SYNTH: c_uint = 0x0F00;
+
+    // Trace events
+    pub const TRC_LOST_RECORDS: c_uint = 0x0001F001;
+    pub const TRC_TRACE_WRAP_BUFFER: c_uint = 0x0001F002;
+    pub const TRC_TRACE_CPU_CHANGE: c_uint = 0x0001F003;
+
+    pub const TRC_SCHED_RUNSTATE_CHANGE: c_uint = 0x00021001;
+    pub const TRC_SCHED_RUNSTATE_MASK: c_uint = 0xFFFFF00F;
+    pub const TRC_SCHED_TO_RUN: c_uint = 0x00021F0F; // Records scheduling a domain, as a mask
+    pub const TRC_SCHED_DOM_WAKE: c_uint = 0x00028004;
+    pub const TRC_SCHED_SWITCH_INFPREV: c_uint = 0x0002800E;
+    pub const TRC_SCHED_SWITCH_INFNEXT: c_uint = 0x0002800F;
+
+    pub const TRC_HVM_VMENTRY: c_uint = 0x00081001;
+    pub const TRC_HVM_VMEXIT: c_uint = 0x00081002;
+    pub const TRC_HVM_VMEXIT64: c_uint = 0x00081102;
+    pub const TRC_HVM_HANDLER: c_uint = 0x00082000;
+    pub const TRC_HVM_HANDLER_MASK: c_uint = 0xFFFFF000;
+    pub const TRC_HVM_RDTSC: c_uint = 0x0008201A;
+
+    pub const TRC_PM_FREQ_CHANGE: c_uint = 0x00801001;
+
+    pub const TRC_SYNTH_SCHED_SWITCH: c_uint = 0x0F000001;
+    pub const TRC_SYNTH_SCHED_WAKING: c_uint = 0x0F000002;
+    pub const TRC_SYNTH_EXIT_LATENCY: c_uint = 0x0F000003;
 }
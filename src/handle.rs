@@ -0,0 +1,284 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    hash::Hash,
+    path::Path,
+    ptr::null_mut,
+    sync::{PoisonError, RwLock},
+};
+
+use fxhash::FxBuildHasher;
+use libc::{c_char, c_int, c_short, strdup};
+
+use crate::{
+    config::{Config, CpuVendor},
+    decoder::{
+        detect_cpu_vendor, get_domain_task, get_known_codes, get_pid_domain,
+        timestamp::{detect_cpu_freq, TscClock},
+        DomainNames, GuestClocks, SyntheticRecords,
+    },
+    ffi::libkshark::Entry,
+    log,
+    store::{Record, TraceStore},
+};
+
+/// Number of info strings kept, enough for the rows shown by the GUI.
+const INFO_CACHE_CAPACITY: usize = 4096;
+
+/// Data handle of a XenTrace stream, stored into `GenericStreamInterface::handle`.
+pub(crate) struct StreamHandle {
+    pub(crate) trace: TraceStore,
+    /// Scheduling events made up from the records of the trace.
+    pub(crate) synthetic: SyntheticRecords,
+    pub(crate) events: EventIds,
+    pub(crate) clock: TscClock,
+    /// Offset (in nanoseconds) of the timestamps, aligned with a guest if configured.
+    pub(crate) time_offset: i64,
+    /// Vendor of the host CPUs, naming the VM exit reasons.
+    pub(crate) vendor: Option<CpuVendor>,
+    pub(crate) domains: DomainNames,
+    pub(crate) config: Config,
+    /// Event names, keyed by event code.
+    pub(crate) names: StringCache<u32>,
+    /// Task names, keyed by PID.
+    pub(crate) tasks: StringCache<c_int>,
+    /// Info strings of the recently shown records, keyed by offset.
+    pub(crate) infos: StringCache<u64>,
+}
+
+impl StreamHandle {
+    pub(crate) fn new(trace: TraceStore, trace_path: &Path) -> Self {
+        let config = Config::load(trace_path);
+        let events = EventIds::new(&trace);
+        let domains = DomainNames::load(trace_path, &config);
+        let clock = TscClock::new(&trace, detect_cpu_freq(&trace, &config));
+        let synthetic = SyntheticRecords::new(&trace, config.pid_scheme);
+        let vendor = detect_cpu_vendor(&trace, &config);
+        let time_offset =
+            GuestClocks::new(&trace).get_time_offset(trace_path, &clock, &config, &domains);
+
+        Self {
+            trace,
+            synthetic,
+            events,
+            clock,
+            time_offset,
+            vendor,
+            domains,
+            config,
+            names: StringCache::default(),
+            tasks: StringCache::default(),
+            infos: StringCache::with_capacity(INFO_CACHE_CAPACITY),
+        }
+    }
+
+    /// Names the task of a PID, as built by the PID scheme of the stream.
+    pub(crate) fn get_task_name(&self, pid: c_int) -> Option<String> {
+        let (dom_id, vcpu) = get_pid_domain(pid, self.config.pid_scheme)?;
+        Some(get_domain_task(dom_id, vcpu, &self.domains))
+    }
+
+    /// Decodes the record of the entry, whose offset is the byte offset in the trace file.
+    /// Synthetic records have a negative offset instead, as `-(source + 1)`
+    /// where `source` is the offset of the record they are made from.
+    pub(crate) fn get_record(&self, entry: &Entry) -> Option<Record> {
+        match u64::try_from(entry.offset) {
+            Ok(offset) => self.trace.get(offset),
+            Err(_) => {
+                let offset = u64::try_from(-(entry.offset + 1)).ok()?;
+                let source = self.trace.get(offset)?;
+                self.synthetic.get(offset, &source, &self.clock)
+            }
+        }
+    }
+}
+
+/// Strings formatted once per stream.
+///
+/// The cache owns its strings until the stream is closed,
+/// KernelShark only receives copies of them.
+#[derive(Default)]
+pub(crate) struct StringCache<K> {
+    strings: RwLock<HashMap<K, CString, FxBuildHasher>>,
+    /// Number of strings above which the cache is emptied, if any.
+    capacity: Option<usize>,
+}
+
+impl<K: Copy + Eq + Hash> StringCache<K> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            strings: RwLock::default(),
+            capacity: Some(capacity),
+        }
+    }
+
+    /// Returns a copy of the string of the key, formatting it on first use.
+    pub(crate) fn get_raw(&self, key: K, format: impl FnOnce() -> Option<String>) -> *mut c_char {
+        {
+            let strings = self.strings.read().unwrap_or_else(PoisonError::into_inner);
+            if let Some(string) = strings.get(&key) {
+                return unsafe { strdup(string.as_ptr()) };
+            }
+        }
+
+        let Some(string) = format().and_then(|string| CString::new(string).ok()) else {
+            return null_mut();
+        };
+
+        let ptr = unsafe { strdup(string.as_ptr()) };
+        let mut strings = self.strings.write().unwrap_or_else(PoisonError::into_inner);
+        if self
+            .capacity
+            .map_or(false, |capacity| strings.len() >= capacity)
+        {
+            strings.clear();
+        }
+
+        let _ = strings.insert(key, string);
+        ptr
+    }
+}
+
+/// Event ID shared by the codes left without one, the last of the 16-bit IDs.
+pub(crate) const UNKNOWN_EVENT_ID: c_short = c_short::MAX;
+
+/// Compact mapping between the 32-bit XenTrace event codes and
+/// the 16-bit event IDs used by KernelShark.
+pub(crate) struct EventIds {
+    codes: Box<[u32]>,
+    ids: HashMap<u32, c_short, FxBuildHasher>,
+    /// Whether some codes did not get an ID of their own.
+    overflow: bool,
+}
+
+impl EventIds {
+    /// Assigns an ID to every event code in the trace, plus the known ones.
+    pub(crate) fn new(trace: &TraceStore) -> Self {
+        Self::from_codes(trace.codes().chain(get_known_codes()))
+    }
+
+    /// The IDs follow the order of the codes, so they are stable between
+    /// traces containing the same set of events. The codes beyond the last
+    /// ID share `UNKNOWN_EVENT_ID`.
+    fn from_codes(codes: impl Iterator<Item = u32>) -> Self {
+        let mut codes = codes
+            .collect::<HashSet<_, FxBuildHasher>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        codes.sort_unstable();
+
+        let overflow = codes.len() > UNKNOWN_EVENT_ID as usize;
+        if overflow {
+            log!(
+                "Warning: {} event codes beyond the first {UNKNOWN_EVENT_ID} are shown with one unknown event ID",
+                codes.len() - UNKNOWN_EVENT_ID as usize
+            );
+            codes.truncate(UNKNOWN_EVENT_ID as usize);
+        }
+
+        let ids = codes.iter().copied().zip(0..UNKNOWN_EVENT_ID).collect();
+        Self {
+            codes: codes.into_boxed_slice(),
+            ids,
+            overflow,
+        }
+    }
+
+    /// Returns the number of event IDs, `UNKNOWN_EVENT_ID` included if used.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.codes.len() + usize::from(self.overflow)
+    }
+
+    #[inline]
+    pub(crate) fn get_id(&self, code: u32) -> Option<c_short> {
+        self.ids.get(&code).copied()
+    }
+
+    /// Returns the ID of the entries of a code, `UNKNOWN_EVENT_ID` if it has none.
+    #[inline]
+    pub(crate) fn get_entry_id(&self, code: u32) -> c_short {
+        self.get_id(code).unwrap_or(UNKNOWN_EVENT_ID)
+    }
+
+    #[inline]
+    pub(crate) fn get_code(&self, id: c_short) -> Option<u32> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|index| self.codes.get(index))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, ffi::CStr};
+
+    use libc::free;
+
+    use super::{EventIds, StringCache, UNKNOWN_EVENT_ID};
+    use crate::{store::TraceStore, util::test::trace_bytes};
+
+    #[test]
+    fn lossless_event_ids() {
+        let trace = TraceStore::from_bytes(&trace_bytes(&[
+            (0x00021011, 10, &[0x0001_0000]),
+            (0x00BC0001, 20, &[]),
+        ]));
+        let events = EventIds::new(&trace);
+
+        for code in [0x00021011, 0x00BC0001, 0x00081002] {
+            let id = events.get_id(code).unwrap();
+            assert!(id >= 0);
+            assert_eq!(events.get_code(id), Some(code));
+        }
+
+        assert_eq!(events.get_id(0x00BC0002), None);
+        assert_eq!(events.get_code(-1), None);
+    }
+
+    #[test]
+    fn overflowing_event_ids() {
+        let events = EventIds::from_codes(0x00BC0000..0x00BD0000);
+        assert_eq!(events.len(), UNKNOWN_EVENT_ID as usize + 1);
+
+        let last = 0x00BC0000 + UNKNOWN_EVENT_ID as u32 - 1;
+        assert_eq!(events.get_entry_id(last), UNKNOWN_EVENT_ID - 1);
+        assert_eq!(events.get_entry_id(last + 1), UNKNOWN_EVENT_ID);
+        assert_eq!(events.get_entry_id(0x00BCFFFF), UNKNOWN_EVENT_ID);
+        assert_eq!(events.get_code(UNKNOWN_EVENT_ID), None);
+    }
+
+    #[test]
+    fn cached_strings() {
+        let cache = StringCache::with_capacity(2);
+        let formats = Cell::new(0);
+        let get = |key: u32| {
+            let ptr = cache.get_raw(key, || {
+                formats.set(formats.get() + 1);
+                (key > 0).then(|| format!("key {key}"))
+            });
+
+            (!ptr.is_null()).then(|| unsafe {
+                let string = CStr::from_ptr(ptr).to_str().unwrap().to_string();
+                free(ptr as _);
+                string
+            })
+        };
+
+        assert_eq!(get(1).as_deref(), Some("key 1"));
+        assert_eq!(get(1).as_deref(), Some("key 1"));
+        assert_eq!(formats.get(), 1);
+
+        // Missing strings are not cached
+        assert_eq!(get(0), None);
+        assert_eq!(get(0), None);
+        assert_eq!(formats.get(), 3);
+
+        // Filling the cache empties it
+        assert_eq!(get(2).as_deref(), Some("key 2"));
+        assert_eq!(get(3).as_deref(), Some("key 3"));
+        assert_eq!(get(1).as_deref(), Some("key 1"));
+        assert_eq!(formats.get(), 6);
+    }
+}
@@ -1,126 +1,856 @@
-use std::ptr::null_mut;
+use std::{iter, mem::size_of, ptr::null_mut};
 
-use libc::{c_char, c_int, c_short, c_void, ssize_t};
-use xentrace_parser::{record::DomainKind, Trace};
+use libc::{
+    c_char, c_int, c_long, c_short, c_void, free, realloc, ssize_t, EFAULT, ENOENT, ENOMEM,
+};
 
 use crate::{
     decoder::{
-        get_record_info, get_record_name, get_record_task,
-        timestamp::{get_env_cpu_freq, tsc_to_ns},
+        get_code_name, get_domain_pid, get_event_code, get_event_fields, get_loss_info,
+        get_record_dump, get_record_extra, get_record_info, read_event_field, DataLoss,
+        ExitHistograms, SyntheticTracker,
     },
     ffi::libkshark::{
-        DataStream, Entry, GenericStreamInterface, KS_EMPTY_BIN, KS_PLUGIN_UNTOUCHED_MASK,
+        DataStream, Entry, EventFieldFormat, GenericStreamInterface, KS_EMPTY_BIN,
+        KS_PLUGIN_UNTOUCHED_MASK,
     },
-    str_into_raw,
+    handle::{StreamHandle, UNKNOWN_EVENT_ID},
+    malloc_array,
+    store::Record,
+    str_from_ptr, str_into_raw,
 };
 
-pub fn get_pid(_stream: *mut DataStream, entry: *mut Entry) -> c_int {
-    Entry::from_ptr(entry)
-        .filter(|entry| entry.visible & KS_PLUGIN_UNTOUCHED_MASK > 0)
-        .map(|entry| entry.pid)
+pub fn get_pid(stream: *mut DataStream, entry: *mut Entry) -> c_int {
+    let Some(entry) = Entry::from_ptr(entry) else {
+        return KS_EMPTY_BIN;
+    };
+
+    // An entry touched by a plugin may no longer carry the original PID
+    if entry.visible & KS_PLUGIN_UNTOUCHED_MASK > 0 {
+        return entry.pid;
+    }
+
+    DataStream::from_ptr(stream)
+        .and_then(DataStream::get_interface)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .and_then(|handle| {
+            let record = handle.get_record(entry)?;
+            Some(get_domain_pid(record.domain(), handle.config.pid_scheme))
+        })
         .unwrap_or(KS_EMPTY_BIN)
 }
 
 pub fn get_event_id(stream: *mut DataStream, entry: *mut Entry) -> c_int {
-    DataStream::from_ptr(stream)
+    let Some((handle, entry)) = DataStream::from_ptr(stream)
         .and_then(DataStream::get_interface)
-        .and_then(GenericStreamInterface::get_data_handler::<Trace>)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
         .zip(Entry::from_ptr(entry))
-        .and_then(|(trace, entry)| trace.get(entry.offset as usize))
-        .and_then(|record| u32::from(record.event().code()).try_into().ok())
-        .unwrap_or(0)
+    else {
+        return KS_EMPTY_BIN;
+    };
+
+    // An entry touched by a plugin may no longer carry the original ID
+    if entry.visible & KS_PLUGIN_UNTOUCHED_MASK > 0 {
+        return entry.event_id.into();
+    }
+
+    handle
+        .get_record(entry)
+        .map(|record| handle.events.get_entry_id(record.event().code().value()))
+        .map(c_int::from)
+        .unwrap_or(KS_EMPTY_BIN)
 }
 
 pub fn get_event_name(stream: *mut DataStream, entry: *mut Entry) -> *mut c_char {
+    let event_id = get_event_id(stream, entry);
+
     DataStream::from_ptr(stream)
         .and_then(DataStream::get_interface)
-        .and_then(GenericStreamInterface::get_data_handler::<Trace>)
-        .zip(Entry::from_ptr(entry))
-        .and_then(|(trace, entry)| trace.get(entry.offset as usize))
-        .and_then(|record| str_into_raw!(get_record_name(record.event())))
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .zip(c_short::try_from(event_id).ok())
+        .and_then(|(handle, event_id)| {
+            // The codes sharing the unknown ID are named after the record
+            let code = match event_id {
+                UNKNOWN_EVENT_ID => Entry::from_ptr(entry)
+                    .and_then(|entry| handle.get_record(entry))
+                    .map(|record| record.event().code().value()),
+                _ => handle.events.get_code(event_id),
+            };
+            Some((handle, code?))
+        })
+        .map(|(handle, code)| {
+            handle
+                .names
+                .get_raw(code, || Some(get_code_name(code.into())))
+        })
         .unwrap_or(null_mut())
 }
 
 pub fn get_task(stream: *mut DataStream, entry: *mut Entry) -> *mut c_char {
+    let pid = get_pid(stream, entry);
+
     DataStream::from_ptr(stream)
         .and_then(DataStream::get_interface)
-        .and_then(GenericStreamInterface::get_data_handler::<Trace>)
-        .zip(Entry::from_ptr(entry))
-        .and_then(|(trace, entry)| trace.get(entry.offset as usize))
-        .and_then(|record| str_into_raw!(get_record_task(record.domain())))
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .map(|handle| handle.tasks.get_raw(pid, || handle.get_task_name(pid)))
         .unwrap_or(null_mut())
 }
 
 pub fn get_info(stream: *mut DataStream, entry: *mut Entry) -> *mut c_char {
     DataStream::from_ptr(stream)
         .and_then(DataStream::get_interface)
-        .and_then(GenericStreamInterface::get_data_handler::<Trace>)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
         .zip(Entry::from_ptr(entry))
-        .and_then(|(trace, entry)| trace.get(entry.offset as usize))
-        .and_then(|record| str_into_raw!(get_record_info(record.event())))
+        .map(|(handle, entry)| {
+            handle.infos.get_raw(entry.offset as u64, || {
+                let record = handle.get_record(entry)?;
+                match handle.config.decoder.raw_info {
+                    true => Some(get_record_extra(record.event())),
+                    false => Some(
+                        get_loss_info(&record)
+                            .unwrap_or_else(|| get_record_info(record.event(), handle.vendor)),
+                    ),
+                }
+            })
+        })
         .unwrap_or(null_mut())
 }
 
-//pub fn dump_entry(stream: *mut DataStream, entry: *mut Entry) -> *mut c_char {
-//    todo!()
-//}
+pub fn dump_entry(stream: *mut DataStream, entry: *mut Entry) -> *mut c_char {
+    DataStream::from_ptr(stream)
+        .and_then(DataStream::get_interface)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .zip(Entry::from_ptr(entry))
+        .and_then(|(handle, entry)| {
+            let record = handle.get_record(entry)?;
+            let task = handle.get_task_name(entry.pid).unwrap_or_default();
+            str_into_raw!(get_record_dump(&record, &task, entry.pid, entry.ts))
+        })
+        .unwrap_or(null_mut())
+}
+
+pub fn find_event_id(stream: *mut DataStream, name: *const c_char) -> c_int {
+    DataStream::from_ptr(stream)
+        .and_then(DataStream::get_interface)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .zip(str_from_ptr!(name).and_then(get_event_code))
+        .and_then(|(handle, code)| handle.events.get_id(code))
+        .map(c_int::from)
+        .unwrap_or(-ENOENT)
+}
+
+pub fn get_all_event_ids(stream: *mut DataStream) -> *mut c_int {
+    let Some(handle) = DataStream::from_ptr(stream)
+        .and_then(DataStream::get_interface)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+    else {
+        return null_mut();
+    };
+
+    let n_events = handle.events.len();
+
+    let ids = malloc_array!(c_int, n_events);
+    if !ids.is_null() {
+        for index in 0..n_events {
+            unsafe { ids.add(index).write(index as c_int) };
+        }
+    }
+
+    ids
+}
+
+pub fn get_all_event_field_names(
+    stream: *mut DataStream,
+    entry: *mut Entry,
+    fields_ptr: *mut *mut *mut c_char,
+) -> c_int {
+    let Some(fields) = DataStream::from_ptr(stream)
+        .and_then(DataStream::get_interface)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .zip(Entry::from_ptr(entry))
+        .and_then(|(handle, entry)| handle.get_record(entry))
+        .and_then(|record| get_event_fields(record.event().code()))
+    else {
+        return 0;
+    };
+
+    if fields_ptr.is_null() {
+        return -EFAULT;
+    }
+
+    let names = malloc_array!(*mut c_char, fields.len());
+    if names.is_null() {
+        return -ENOMEM;
+    }
+
+    for (index, field) in fields.iter().enumerate() {
+        let name = str_into_raw!(field.name).unwrap_or(null_mut());
+        unsafe { names.add(index).write(name) };
+    }
+
+    unsafe { *fields_ptr = names };
+    fields.len().try_into().unwrap_or(c_int::MAX)
+}
+
+pub fn get_event_field_type(
+    stream: *mut DataStream,
+    entry: *mut Entry,
+    field: *const c_char,
+) -> EventFieldFormat {
+    DataStream::from_ptr(stream)
+        .and_then(DataStream::get_interface)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .zip(Entry::from_ptr(entry))
+        .and_then(|(handle, entry)| handle.get_record(entry))
+        .and_then(|record| get_event_fields(record.event().code()))
+        .zip(str_from_ptr!(field))
+        .filter(|(fields, name)| fields.iter().any(|field| field.name == *name))
+        .map(|_| EventFieldFormat::Integer)
+        .unwrap_or(EventFieldFormat::Invalid)
+}
+
+pub fn read_event_field_int64(
+    stream: *mut DataStream,
+    entry: *mut Entry,
+    field: *const c_char,
+    value_ptr: *mut i64,
+) -> c_int {
+    if value_ptr.is_null() {
+        return -EFAULT;
+    }
+
+    let Some(record) = DataStream::from_ptr(stream)
+        .and_then(DataStream::get_interface)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+        .zip(Entry::from_ptr(entry))
+        .and_then(|(handle, entry)| handle.get_record(entry))
+    else {
+        return -EFAULT;
+    };
+
+    let Some(value) = str_from_ptr!(field).and_then(|name| read_event_field(record.event(), name))
+    else {
+        return -ENOENT;
+    };
+
+    unsafe { *value_ptr = value };
+    0
+}
+
+/// Index of the per-CPU corrections in the time calibration constants,
+/// after the offset of the whole stream.
+const CALIB_CPU_SKEW: usize = 1;
+
+/// Adds the offset of the stream, the first time calibration constant.
+///
+/// KernelShark updates that constant when the stream is shifted in time,
+/// so this function follows the layout of its own `kshark_offset_calib`.
+pub extern "C" fn calibrate_ts(ts: *mut c_long, argv: *mut c_long) {
+    if let Some((ts, offset)) = unsafe { ts.as_mut().zip(argv.as_ref()) } {
+        *ts = ts.saturating_add(*offset);
+    }
+}
+
+/// Installs the time calibration of the stream: its offset
+/// followed by the correction of each CPU.
+pub fn init_calibration(stream: &mut DataStream, handle: &StreamHandle) -> Result<(), c_int> {
+    let config = &handle.config;
+    let cpu_count = handle.trace.cpu_count() as usize;
+    let len = CALIB_CPU_SKEW + cpu_count;
+
+    let calib_array = malloc_array!(c_long, len);
+    if calib_array.is_null() {
+        return Err(-ENOMEM);
+    }
+
+    unsafe {
+        calib_array.write(handle.time_offset as c_long);
+        for cpu in 0..cpu_count {
+            let cpu_skew = config
+                .cpu_skew
+                .get(&(cpu as u32))
+                .copied()
+                .unwrap_or_default();
+            calib_array
+                .add(CALIB_CPU_SKEW + cpu)
+                .write(cpu_skew as c_long);
+        }
+    }
+
+    stream.calib = Some(calibrate_ts);
+    stream.calib_array = calib_array;
+    stream.calib_array_size = len;
+    Ok(())
+}
+
+/// State of a load: summaries of the records and the events made up from them.
+struct LoadState<'a> {
+    loss: DataLoss,
+    exits: ExitHistograms,
+    synthetic: SyntheticTracker<'a>,
+}
+
+impl<'a> LoadState<'a> {
+    fn new(handle: &'a StreamHandle) -> Self {
+        Self {
+            loss: DataLoss::default(),
+            exits: ExitHistograms::default(),
+            synthetic: handle.synthetic.tracker(&handle.clock),
+        }
+    }
+
+    fn add(&mut self, record: &Record) {
+        self.loss.add(record);
+        self.exits.add(record);
+    }
+
+    /// Reports the summaries, and keeps the made up events for the lookups.
+    fn finish(self, handle: &StreamHandle) {
+        self.loss.report();
+        self.exits.report(handle.vendor);
+        self.synthetic.finish();
+    }
+}
+
+/// Values of an entry, shared by the entry and the matrix loaders.
+struct Row {
+    offset: i64,
+    cpu: c_short,
+    pid: c_int,
+    event_id: c_short,
+    ts: i64,
+}
+
+/// Decodes the rows of the stream in order, registering the PIDs of the tasks.
+/// Returns the number of rows, unless `add_row` fails on one.
+fn load_rows(
+    stream: &DataStream,
+    handle: &StreamHandle,
+    mut add_row: impl FnMut(usize, Row) -> bool,
+) -> Option<usize> {
+    let calib_array = stream.get_calib_array();
+    let pid_scheme = handle.config.pid_scheme;
+
+    // The per-CPU corrections follow the offset of the stream, and they
+    // are part of the merge key so that the timestamps keep growing
+    let cpu_skew = move |cpu: u32| {
+        usize::try_from(cpu)
+            .ok()
+            .and_then(|cpu| calib_array.get(CALIB_CPU_SKEW + cpu))
+            .copied()
+            .unwrap_or_default()
+    };
+    let to_ns = move |cpu, tsc| handle.clock.to_ns(cpu, tsc).saturating_add(cpu_skew(cpu));
+
+    let mut state = LoadState::new(handle);
+    let mut index = 0;
+
+    for (offset, record) in handle.trace.iter_sorted_by_key(to_ns) {
+        // Each synthetic record follows its source one
+        let synthetic = state
+            .synthetic
+            .add(offset, &record)
+            .map(|synthetic| (-(offset as i64) - 1, synthetic));
+
+        for (offset, record) in iter::once((offset as i64, record)).chain(synthetic) {
+            let pid = get_domain_pid(record.domain(), pid_scheme);
+            if pid != stream.idle_pid {
+                let _ = stream.add_task_id(pid);
+            }
+
+            state.add(&record);
+
+            let row = Row {
+                offset,
+                cpu: record.cpu().try_into().unwrap_or(c_short::MAX),
+                pid,
+                event_id: handle.events.get_entry_id(record.event().code().value()),
+                ts: stream.calibrate(to_ns(record.cpu(), record.event().tsc())),
+            };
+
+            if !add_row(index, row) {
+                return None;
+            }
+            index += 1;
+        }
+    }
+
+    state.finish(handle);
+    Some(index)
+}
 
 pub fn load_entries(
     stream: *mut DataStream,
     _: *mut c_void,
     rows_ptr: *mut *mut *mut Entry,
 ) -> ssize_t {
-    let Some(stream) = DataStream::from_ptr(stream) else {
+    let Some(stream) = DataStream::from_ptr(stream).filter(|_| !rows_ptr.is_null()) else {
         return -1;
     };
 
-    let Some(trace) = stream
+    let Some(handle) = stream
         .get_interface()
-        .and_then(GenericStreamInterface::get_data_handler::<Trace>)
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
     else {
         return -1;
     };
 
-    let rows = {
-        let cpu_qhz = get_env_cpu_freq();
-        let first_tsc = trace.first().map(|record| record.event().tsc());
-
-        let idle_domid = u16::from(DomainKind::Zero) as i32; // Switch PID with the Dom0
-        let host_domid = u16::from(DomainKind::Idle) as i32; // Switch PID with the Idle
-        let _ = stream.add_task_id(host_domid);
-
-        trace
-            .iter()
-            .zip(0..)
-            .map(|(record, index)| {
-                let mut entry = Box::<Entry>::default();
-
-                entry.offset = index;
-                entry.stream_id = stream.stream_id;
-                entry.cpu = record.cpu().try_into().unwrap_or(c_short::MAX);
-                entry.ts = tsc_to_ns(record.event().tsc(), cpu_qhz, first_tsc);
-                entry.event_id = u32::from(record.event().code())
-                    .try_into()
-                    .unwrap_or(c_short::MAX);
-
-                entry.pid = match record.domain().kind() {
-                    DomainKind::Idle => idle_domid,
-                    DomainKind::Zero => host_domid,
-                    domain => {
-                        let dom_id = u16::from(domain) as i32;
-                        let _ = stream.add_task_id(dom_id);
-                        dom_id
-                    }
-                };
+    // KernelShark releases each entry on its own, so they cannot be carved
+    // out of a single block: every entry gets its own allocation, and the
+    // array grows past the records of the trace as the synthetic ones come.
+    let Some(mut rows) = Column::new(rows_ptr, handle.trace.record_count()) else {
+        return -(ENOMEM as ssize_t);
+    };
 
-                Box::into_raw(entry)
-            })
-            .collect::<Box<[*mut Entry]>>()
+    let mut count = 0;
+    let len = load_rows(stream, handle, |index, row| {
+        let entry = malloc_array!(Entry, 1);
+        if entry.is_null() {
+            return false;
+        }
+
+        unsafe {
+            entry.write(Entry {
+                offset: row.offset,
+                stream_id: stream.stream_id,
+                cpu: row.cpu,
+                pid: row.pid,
+                event_id: row.event_id,
+                ts: row.ts,
+                ..Default::default()
+            });
+        }
+
+        if !rows.set(index, entry) {
+            unsafe { free(entry as _) };
+            return false;
+        }
+
+        count += 1;
+        true
+    });
+
+    let Some(len) = len else {
+        (0..count).for_each(|index| unsafe { free(*rows.ptr.add(index) as _) });
+        return -(ENOMEM as ssize_t);
     };
 
-    unsafe {
-        *rows_ptr = Box::into_raw(rows) as _;
+    rows.store(rows_ptr);
+    len.try_into().unwrap_or(ssize_t::MAX)
+}
+
+/// Column of the data matrix, handed over to the caller once filled.
+struct Column<T> {
+    ptr: *mut T,
+    capacity: usize,
+}
+
+impl<T> Column<T> {
+    /// Allocates the column, unless the caller did not ask for it.
+    fn new(column_ptr: *mut *mut T, capacity: usize) -> Option<Self> {
+        if column_ptr.is_null() {
+            return Some(Self {
+                ptr: null_mut(),
+                capacity: 0,
+            });
+        }
+
+        let capacity = capacity.max(1);
+        let ptr = malloc_array!(T, capacity);
+        (!ptr.is_null()).then_some(Self { ptr, capacity })
+    }
+
+    /// Writes the value of a row, doubling the column when it is full.
+    /// Returns `false` if the column cannot grow.
+    #[inline]
+    fn set(&mut self, index: usize, value: T) -> bool {
+        if self.ptr.is_null() {
+            return true;
+        }
+
+        if index >= self.capacity {
+            let capacity = (index + 1).max(self.capacity * 2);
+            let ptr = unsafe { realloc(self.ptr as _, capacity * size_of::<T>()) } as *mut T;
+            if ptr.is_null() {
+                return false;
+            }
+
+            self.ptr = ptr;
+            self.capacity = capacity;
+        }
+
+        unsafe { self.ptr.add(index).write(value) };
+        true
+    }
+
+    /// Hands the column over to the caller.
+    fn store(self, column_ptr: *mut *mut T) {
+        if !column_ptr.is_null() {
+            unsafe { *column_ptr = self.ptr };
+            std::mem::forget(self);
+        }
+    }
+}
+
+impl<T> Drop for Column<T> {
+    fn drop(&mut self) {
+        unsafe { free(self.ptr as _) };
+    }
+}
+
+pub fn load_matrix(
+    stream: *mut DataStream,
+    _: *mut c_void,
+    event_array: *mut *mut i16,
+    cpu_array: *mut *mut i16,
+    pid_array: *mut *mut i32,
+    offset_array: *mut *mut i64,
+    ts_array: *mut *mut i64,
+) -> ssize_t {
+    let Some(stream) = DataStream::from_ptr(stream) else {
+        return -(EFAULT as ssize_t);
+    };
+
+    let Some(handle) = stream
+        .get_interface()
+        .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+    else {
+        return -(EFAULT as ssize_t);
+    };
+
+    let capacity = handle.trace.record_count();
+    let (Some(mut events), Some(mut cpus), Some(mut pids), Some(mut offsets), Some(mut ts)) = (
+        Column::new(event_array, capacity),
+        Column::new(cpu_array, capacity),
+        Column::new(pid_array, capacity),
+        Column::new(offset_array, capacity),
+        Column::new(ts_array, capacity),
+    ) else {
+        return -(ENOMEM as ssize_t);
+    };
+
+    let len = load_rows(stream, handle, |index, row| {
+        events.set(index, row.event_id)
+            && cpus.set(index, row.cpu)
+            && pids.set(index, row.pid)
+            && offsets.set(index, row.offset)
+            && ts.set(index, row.ts)
+    });
+
+    let Some(len) = len else {
+        return -(ENOMEM as ssize_t);
+    };
+
+    events.store(event_array);
+    cpus.store(cpu_array);
+    pids.store(pid_array);
+    offsets.store(offset_array);
+    ts.store(ts_array);
+
+    len.try_into().unwrap_or(ssize_t::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::CStr, path::Path, ptr::null_mut, slice, time::Instant};
+
+    use libc::{c_char, free};
+
+    use super::{
+        dump_entry, get_all_event_field_names, get_all_event_ids, get_event_name, get_info,
+        get_task, init_calibration, load_entries, load_matrix,
+    };
+    use crate::{
+        config::PidScheme,
+        ffi::libkshark::{DataStream, GenericStreamInterface},
+        handle::StreamHandle,
+        store::TraceStore,
+        util::test::trace_bytes,
+    };
+
+    /// Opens a stream on the given records, as done by `kshark_input_initializer`.
+    fn with_stream<R>(records: &[(u32, u64, &[u32])], f: impl FnOnce(&mut DataStream) -> R) -> R {
+        let trace = TraceStore::from_bytes(&trace_bytes(records));
+        let handle = Box::new(StreamHandle::new(trace, Path::new("")));
+
+        let mut interface = GenericStreamInterface {
+            handle: Box::into_raw(handle) as _,
+            ..Default::default()
+        };
+        let mut stream = DataStream {
+            interface: &mut interface,
+            ..Default::default()
+        };
+
+        let result = f(&mut stream);
+        drop(unsafe { Box::from_raw(interface.handle as *mut StreamHandle) });
+        result
+    }
+
+    #[test]
+    fn matrix_columns() {
+        let records: &[(u32, u64, &[u32])] =
+            &[(0x00021011, 10, &[0x0001_0002]), (0x00081002, 20, &[])];
+
+        with_stream(records, |stream| {
+            let (mut events, mut pids, mut offsets) = (null_mut(), null_mut(), null_mut());
+            let len = load_matrix(
+                stream,
+                null_mut(),
+                &mut events,
+                null_mut(),
+                &mut pids,
+                &mut offsets,
+                null_mut(),
+            );
+            assert_eq!(len, 2);
+
+            let handle = stream
+                .get_interface()
+                .and_then(GenericStreamInterface::get_data_handler::<StreamHandle>)
+                .unwrap();
+
+            unsafe {
+                let events = slice::from_raw_parts(events, 2);
+                assert_eq!(handle.events.get_code(events[0]), Some(0x00021011));
+                assert_eq!(handle.events.get_code(events[1]), Some(0x00081002));
+                // No domain is scheduled yet, so the records belong to the default one
+                assert_eq!(slice::from_raw_parts(pids, 2), &[32768 << 15; 2]);
+                // The offsets are the byte offsets of the records in the file
+                assert_eq!(slice::from_raw_parts(offsets, 2), &[0, 16]);
+
+                free(events.as_ptr() as _);
+                free(pids as _);
+                free(offsets as _);
+            }
+        });
+    }
+
+    #[test]
+    fn calibrated_timestamps() {
+        // 24 cycles are 10ns at the default frequency
+        let records: &[(u32, u64, &[u32])] = &[
+            (0x00081002, 0, &[]),
+            (0x00081002, 240, &[]),
+            (0x0001F003, 0, &[1]),
+            (0x00081002, 120, &[]),
+        ];
+
+        with_stream(records, |stream| {
+            let handle =
+                unsafe { &mut *(stream.interface.as_ref().unwrap().handle as *mut StreamHandle) };
+            handle.time_offset = 1_000;
+            handle.config.cpu_skew.insert(1, 100);
+            assert_eq!(init_calibration(stream, handle), Ok(()));
+            assert_eq!(stream.get_calib_array(), &[1_000, 0, 100]);
+
+            let load_ts = |stream: &mut DataStream| {
+                let mut ts = null_mut();
+                let len = load_matrix(
+                    stream,
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    &mut ts,
+                );
+
+                let column = unsafe { slice::from_raw_parts(ts, len as usize) }.to_vec();
+                unsafe { free(ts as _) };
+                column
+            };
+
+            // The corrected CPU 1 record comes last
+            assert_eq!(load_ts(stream), &[1_000, 1_100, 1_150]);
+
+            // KernelShark shifts the stream by updating its offset
+            unsafe { *stream.calib_array = -1_000 };
+            assert_eq!(load_ts(stream), &[-1_000, -900, -850]);
+
+            unsafe { free(stream.calib_array as _) };
+        });
     }
 
-    trace.record_count().try_into().unwrap_or(ssize_t::MAX)
+    #[test]
+    fn synthetic_entries() {
+        let records: &[(u32, u64, &[u32])] = &[
+            (0x00021011, 10, &[0x0001_0000]), // d1/v0 running -> runnable
+            (0x00021101, 10, &[0x0002_0001]), // d2/v1 runnable -> running
+        ];
+
+        with_stream(records, |stream| {
+            let mut rows = null_mut();
+            assert_eq!(load_entries(stream, null_mut(), &mut rows), 3);
+
+            let entries = unsafe { slice::from_raw_parts(rows, 3) };
+            let switch = entries[2];
+            // Made from the record at offset 16, it grew the array past the records
+            assert_eq!(unsafe { (*switch).offset }, -17);
+            assert_eq!(unsafe { (*switch).pid }, 1 << 15);
+            assert_eq!(
+                take_string(get_event_name(stream, switch)),
+                "sched/sched_switch"
+            );
+            assert_eq!(
+                take_string(get_info(stream, switch)),
+                "prev_pid=32768 prev_state=R next_pid=65537 prev_dom=1 prev_vcpu=0 next_dom=2 next_vcpu=1"
+            );
+
+            entries
+                .iter()
+                .for_each(|entry| unsafe { free(*entry as _) });
+            unsafe { free(rows as _) };
+        });
+    }
+
+    #[test]
+    fn dump_task_from_pid() {
+        let records: &[(u32, u64, &[u32])] = &[(0x00021002, 24, &[0x0001_0002])];
+
+        with_stream(records, |stream| {
+            let handle =
+                unsafe { &mut *(stream.interface.as_ref().unwrap().handle as *mut StreamHandle) };
+            handle.config.pid_scheme = PidScheme::Domain;
+
+            let mut rows = null_mut();
+            assert_eq!(load_entries(stream, null_mut(), &mut rows), 1);
+
+            let entry = unsafe { *rows };
+            assert_eq!(take_string(get_task(stream, entry)), "d1");
+            let dump = take_string(dump_entry(stream, entry));
+            assert!(dump.starts_with("0 d1-1; CPU 0; TSC 24;"), "{dump}");
+
+            unsafe {
+                free(entry as _);
+                free(rows as _);
+            }
+        });
+    }
+
+    /// Takes a string returned to KernelShark, releasing it as KernelShark does.
+    /// The test allocator makes `free` abort on memory not from `malloc`.
+    fn take_string(ptr: *mut c_char) -> String {
+        assert!(!ptr.is_null());
+        let string = unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string();
+        unsafe { free(ptr as _) };
+        string
+    }
+
+    #[test]
+    fn free_returned_memory() {
+        let records: &[(u32, u64, &[u32])] = &[
+            (0x00021002, 10, &[0x0001_0002]),
+            (0x00081002, 20, &[]),
+            (0x00BC0001, 30, &[0xAB]),
+        ];
+
+        with_stream(records, |stream| {
+            let mut rows = null_mut();
+            let len = load_entries(stream, null_mut(), &mut rows);
+            assert_eq!(len, 3);
+
+            let entries = unsafe { slice::from_raw_parts(rows, 3) };
+            for entry in entries {
+                let _ = take_string(get_event_name(stream, *entry));
+                let _ = take_string(get_task(stream, *entry));
+                let _ = take_string(get_info(stream, *entry));
+                let _ = take_string(dump_entry(stream, *entry));
+
+                let mut fields = null_mut();
+                let n_fields = get_all_event_field_names(stream, *entry, &mut fields);
+                if n_fields > 0 {
+                    let fields = unsafe { slice::from_raw_parts(fields, n_fields as usize) };
+                    fields.iter().for_each(|field| drop(take_string(*field)));
+                    unsafe { free(fields.as_ptr() as _) };
+                }
+            }
+
+            assert_eq!(take_string(get_task(stream, entries[0])), "d1/v2");
+            assert_eq!(take_string(get_info(stream, entries[2])), "0x000000AB");
+
+            let ids = get_all_event_ids(stream);
+            assert!(!ids.is_null());
+            unsafe { free(ids as _) };
+
+            entries
+                .iter()
+                .for_each(|entry| unsafe { free(*entry as _) });
+            unsafe { free(rows as _) };
+        });
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture load_entries_benchmark`.
+    #[test]
+    #[ignore]
+    fn load_entries_benchmark() {
+        const RECORDS: u32 = 5_000_000;
+
+        // Switches among 8 domains, with 4 virtual CPUs each, on 8 CPUs
+        // whose buffers of 1000 records are interleaved in the file
+        let domains = (0..32)
+            .map(|n| [((n % 8) << 16) | (n / 8)])
+            .collect::<Vec<_>>();
+        let cpus = (0..8).map(|cpu| [cpu]).collect::<Vec<_>>();
+        let records = (0..RECORDS)
+            .flat_map(|i| {
+                let (buffer, index) = (i / 1000, i % 1000);
+                let cpu = buffer % 8;
+                let tsc = ((buffer / 8 * 1000 + index) * 100 + cpu) as u64;
+
+                let cpu_change = (index == 0).then(|| (0x0001F003, tsc, &cpus[cpu as usize][..]));
+                let record = match i % 4 {
+                    0 => (0x00021002, tsc, &domains[(i / 4 % 32) as usize][..]),
+                    1 => (0x00081002, tsc, &[][..]),
+                    _ => (0x00082002, tsc, &[0x1234, 0x5678][..]),
+                };
+
+                cpu_change.into_iter().chain([record])
+            })
+            .collect::<Vec<_>>();
+
+        with_stream(&records, |stream| {
+            let mut rows = null_mut();
+            let start = Instant::now();
+            let len = load_entries(stream, null_mut(), &mut rows);
+            let elapsed = start.elapsed();
+
+            assert_eq!(len, RECORDS as isize);
+            println!(
+                "load_entries: {RECORDS} records in {elapsed:?} ({:?}/record)",
+                elapsed / RECORDS
+            );
+
+            unsafe {
+                for entry in slice::from_raw_parts(rows, len as usize) {
+                    free(*entry as _);
+                }
+                free(rows as _);
+            }
+
+            let mut columns = [null_mut(); 5];
+            let [events, cpus, pids, offsets, ts] = &mut columns;
+            let start = Instant::now();
+            let len = load_matrix(
+                stream,
+                null_mut(),
+                events as *mut _ as _,
+                cpus as *mut _ as _,
+                pids as *mut _ as _,
+                offsets as *mut _ as _,
+                ts as *mut _ as _,
+            );
+            let elapsed = start.elapsed();
+
+            assert_eq!(len, RECORDS as isize);
+            println!(
+                "load_matrix: {RECORDS} records in {elapsed:?} ({:?}/record)",
+                elapsed / RECORDS
+            );
+
+            columns
+                .into_iter()
+                .for_each(|column| unsafe { free(column) });
+        });
+    }
 }
@@ -17,23 +17,34 @@
  * Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301
  * USA
  */
+mod config;
 mod decoder;
 mod ffi;
+mod handle;
 mod interface;
+mod store;
 mod util;
 
 use std::{fs, io::Read as _, path::Path, ptr::null_mut};
 
 use libc::{c_char, c_int, EFAULT, ENOENT};
-use xentrace_parser::Trace;
 
 use self::{
-    ffi::libkshark::{DataStream, GenericStreamInterface},
-    interface::{get_event_id, get_event_name, get_info, get_pid, get_task, load_entries},
+    decoder::get_idle_pid,
+    ffi::{
+        libkshark::{DataStream, GenericStreamInterface},
+        xen::trace::TRC_TRACE_CPU_CHANGE,
+    },
+    handle::StreamHandle,
+    interface::{
+        dump_entry, find_event_id, get_all_event_field_names, get_all_event_ids,
+        get_event_field_type, get_event_id, get_event_name, get_info, get_pid, get_task,
+        init_calibration, load_entries, load_matrix, read_event_field_int64,
+    },
+    store::TraceStore,
 };
 
-const TRC_TRACE_CPU_CHANGE: u32 = 0x0001F003;
-static KSHARK_FORMAT_NAME: &str = "xentrace_binary";
+static KSHARK_FORMAT_NAME: &str = "xentrace_binary\0";
 
 // KSHARK_INPUT_INITIALIZER @ libkshark-plugin.h
 #[no_mangle]
@@ -42,20 +53,23 @@ pub extern "C" fn kshark_input_initializer(stream: *mut DataStream) -> c_int {
         return -EFAULT;
     };
 
-    let trace = {
+    let handle = {
         let Some(bin_path) = stream.get_file_path() else {
             return -EFAULT;
         };
 
-        match Trace::from_file(bin_path) {
-            Ok(trace) => Box::new(trace),
-            Err(e) => return e.raw_os_error().unwrap_or(-ENOENT),
+        match TraceStore::open(Path::new(bin_path)) {
+            Ok(trace) => Box::new(StreamHandle::new(trace, Path::new(bin_path))),
+            Err(e) => return -e.raw_os_error().unwrap_or(ENOENT),
         }
     };
 
-    stream.idle_pid = 0;
-    stream.n_cpus = trace.cpu_count().try_into().unwrap_or(c_int::MAX);
-    stream.n_events = trace.record_count().try_into().unwrap_or(c_int::MAX);
+    stream.idle_pid = get_idle_pid(handle.config.pid_scheme);
+    stream.n_cpus = handle.trace.cpu_count().try_into().unwrap_or(c_int::MAX);
+    stream.n_events = handle.events.len().try_into().unwrap_or(c_int::MAX);
+    if let Err(e) = init_calibration(stream, &handle) {
+        return e;
+    }
 
     stream.interface = {
         let mut interface = Box::<GenericStreamInterface>::default();
@@ -65,9 +79,16 @@ pub extern "C" fn kshark_input_initializer(stream: *mut DataStream) -> c_int {
         interface.get_event_name = get_event_name as _;
         interface.get_task = get_task as _;
         interface.get_info = get_info as _;
-        //interface.dump_entry = dump_entry as _;
+        interface.find_event_id = find_event_id as _;
+        interface.get_all_event_ids = get_all_event_ids as _;
+        interface.dump_entry = dump_entry as _;
+        interface.get_all_event_field_names = get_all_event_field_names as _;
+        interface.get_event_field_type = get_event_field_type as _;
+        interface.read_event_field_int64 = read_event_field_int64 as _;
+        // "read_record_field_int64" stays unset, as this stream has no record type known to C
         interface.load_entries = load_entries as _;
-        interface.handle = Box::into_raw(trace) as _;
+        interface.load_matrix = load_matrix as _;
+        interface.handle = Box::into_raw(handle) as _;
 
         Box::into_raw(interface)
     };
@@ -81,7 +102,7 @@ pub extern "C" fn kshark_input_deinitializer(stream: *mut DataStream) {
     if let Some(stream) = DataStream::from_ptr_mut(stream) {
         let interface_exists = if let Some(interface) = stream.get_interface_mut() {
             if !interface.handle.is_null() {
-                let _ = unsafe { Box::<Trace>::from_raw(interface.handle as _) }; // Drop it
+                let _ = unsafe { Box::<StreamHandle>::from_raw(interface.handle as _) }; // Drop it
                 interface.handle = null_mut();
             }
 
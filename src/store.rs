@@ -0,0 +1,621 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
+    fs::File,
+    io,
+    path::Path,
+};
+
+use fxhash::FxBuildHasher;
+use memmap2::Mmap;
+use xentrace_parser::record::{Domain, EventCode, EVENT_EXTRA_CAPACITY};
+
+use crate::ffi::xen::trace::{TRC_SCHED_TO_RUN, TRC_TRACE_CPU_CHANGE};
+
+/// Number of records after which a per-CPU buffer is split, bounding the walk
+/// needed to find a record from the start of its buffer.
+const CHECKPOINT_RECORDS: usize = 4096;
+
+const HEADER_SIZE: usize = 4;
+const TSC_SIZE: usize = 8;
+const EXTRA_SIZE: usize = 4;
+
+/// An event decoded from the trace file.
+#[derive(Clone, Debug)]
+pub(crate) struct Event {
+    code: EventCode,
+    tsc: u64,
+    extra: [Option<u32>; EVENT_EXTRA_CAPACITY],
+}
+
+impl Event {
+    pub(crate) fn new(code: u32, tsc: u64, extra: [Option<u32>; EVENT_EXTRA_CAPACITY]) -> Self {
+        Self {
+            code: EventCode::from(code),
+            tsc,
+            extra,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn code(&self) -> EventCode {
+        self.code
+    }
+
+    #[inline]
+    pub(crate) fn tsc(&self) -> u64 {
+        self.tsc
+    }
+
+    #[inline]
+    pub(crate) fn extra(&self) -> &[Option<u32>; EVENT_EXTRA_CAPACITY] {
+        &self.extra
+    }
+}
+
+/// A record decoded from the trace file, along with the CPU
+/// and the domain it belongs to.
+#[derive(Clone, Debug)]
+pub(crate) struct Record {
+    cpu: u32,
+    domain: Domain,
+    event: Event,
+}
+
+impl Record {
+    pub(crate) fn new(cpu: u32, domain: Domain, event: Event) -> Self {
+        Self { cpu, domain, event }
+    }
+
+    #[inline]
+    pub(crate) fn cpu(&self) -> u32 {
+        self.cpu
+    }
+
+    #[inline]
+    pub(crate) fn domain(&self) -> &Domain {
+        &self.domain
+    }
+
+    #[inline]
+    pub(crate) fn event(&self) -> &Event {
+        &self.event
+    }
+}
+
+/// Header of a record, as laid out in the trace file.
+struct Header {
+    code: u32,
+    tsc: Option<u64>,
+    extra_len: usize,
+    size: usize,
+}
+
+/// A per-CPU buffer, that is the records between two `gen:cpu_change` ones,
+/// or a part of it. It holds the context needed to walk its records on its own.
+#[derive(Clone, Copy, Debug)]
+struct Buffer {
+    start: u64,
+    end: u64,
+    cpu: u32,
+    /// Domain scheduled on the CPU at the start of the buffer.
+    domain: Domain,
+    /// TSC inherited by the first record, if it does not carry one.
+    tsc: u64,
+}
+
+/// Where a record is, along with its context.
+struct Position {
+    offset: u64,
+    tsc: u64,
+    cpu: u32,
+    domain: Domain,
+}
+
+/// Memory-mapped XenTrace file, whose records are decoded on demand.
+///
+/// The records are addressed by their byte offset in the file. Only the
+/// context of each per-CPU buffer is kept in memory, the buffers being split
+/// every `CHECKPOINT_RECORDS` records: a record is decoded by walking its
+/// buffer from the start, tracking the scheduled domain and the TSC.
+pub(crate) struct TraceStore {
+    data: Mmap,
+    /// Per-CPU buffers, in file order.
+    buffers: Box<[Buffer]>,
+    /// Event codes found in the trace.
+    codes: HashSet<u32, FxBuildHasher>,
+    /// TSC of the first record of each CPU, in file order.
+    cpu_first_tsc: HashMap<u32, u64, FxBuildHasher>,
+    /// Number of records whose TSC precedes the previous one of the same CPU.
+    tsc_regressions: BTreeMap<u32, usize>,
+    first_tsc: Option<u64>,
+    record_count: usize,
+    cpu_count: u32,
+}
+
+impl TraceStore {
+    /// Maps the trace file and indexes the context of its records.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let data = unsafe { Mmap::map(&file)? };
+        Ok(Self::new(data, CHECKPOINT_RECORDS))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        Self::new(test::map_bytes(bytes), CHECKPOINT_RECORDS)
+    }
+
+    fn new(data: Mmap, checkpoint_records: usize) -> Self {
+        let mut buffers = Vec::new();
+        let mut cpu_domains = HashMap::<u32, Domain, FxBuildHasher>::default();
+        let mut codes = HashSet::default();
+        let mut cpu_last_tsc = HashMap::<u32, u64, FxBuildHasher>::default();
+        let mut cpu_first_tsc = HashMap::default();
+        let mut tsc_regressions = BTreeMap::new();
+        let mut first_tsc = None::<u64>;
+        let mut record_count = 0;
+        let mut max_cpu = None;
+
+        // Records before the first `gen:cpu_change` belong to the CPU 0
+        let mut buffer = Buffer {
+            start: 0,
+            end: 0,
+            cpu: 0,
+            domain: Domain::default(),
+            tsc: 0,
+        };
+        let mut buffer_records = 0;
+        let mut last_tsc = 0;
+
+        // The trace is truncated to the last readable record
+        let mut offset = 0;
+        while let Some(header) = read_header(&data, offset) {
+            let cpu = buffer.cpu;
+            let extra_0 = match header.extra_len {
+                0 => None,
+                _ => read_u32(&data, offset + header.size - header.extra_len * EXTRA_SIZE),
+            };
+
+            // Records without a TSC inherit the one of the previous record
+            let tsc = header.tsc.unwrap_or(last_tsc);
+
+            if header.code == TRC_TRACE_CPU_CHANGE {
+                buffer.end = offset as u64;
+                if buffer.end > buffer.start {
+                    buffers.push(buffer);
+                }
+
+                let cpu = extra_0.unwrap_or(0);
+                buffer = Buffer {
+                    start: (offset + header.size) as u64,
+                    end: 0,
+                    cpu,
+                    domain: cpu_domains.get(&cpu).copied().unwrap_or_default(),
+                    tsc,
+                };
+                buffer_records = 0;
+            } else {
+                if buffer_records == checkpoint_records {
+                    buffer.end = offset as u64;
+                    buffers.push(buffer);
+
+                    buffer = Buffer {
+                        start: offset as u64,
+                        end: 0,
+                        cpu,
+                        domain: cpu_domains.get(&cpu).copied().unwrap_or_default(),
+                        tsc: last_tsc,
+                    };
+                    buffer_records = 0;
+                }
+
+                if header.code == (header.code & TRC_SCHED_TO_RUN) {
+                    let _ = cpu_domains.insert(cpu, Domain::from(extra_0.unwrap_or(0)));
+                }
+
+                // The inherited TSC counts too, as the records are merged by it
+                if let Some(last_tsc) = cpu_last_tsc.insert(cpu, tsc) {
+                    if tsc < last_tsc {
+                        *tsc_regressions.entry(cpu).or_default() += 1;
+                    }
+                }
+
+                if header.tsc.is_some() {
+                    let _ = cpu_first_tsc.entry(cpu).or_insert(tsc);
+                    first_tsc = Some(first_tsc.map_or(tsc, |first| first.min(tsc)));
+                }
+
+                let _ = codes.insert(header.code);
+                buffer_records += 1;
+                record_count += 1;
+                max_cpu = max_cpu.max(Some(cpu));
+            }
+
+            last_tsc = tsc;
+            offset += header.size;
+        }
+
+        buffer.end = offset as u64;
+        if buffer.end > buffer.start {
+            buffers.push(buffer);
+        }
+
+        Self {
+            data,
+            buffers: buffers.into_boxed_slice(),
+            codes,
+            cpu_first_tsc,
+            tsc_regressions,
+            first_tsc,
+            record_count,
+            cpu_count: max_cpu.map_or(0, |cpu| cpu.saturating_add(1)),
+        }
+    }
+
+    /// Returns the number of records, `gen:cpu_change` ones excluded.
+    #[inline]
+    pub(crate) fn record_count(&self) -> usize {
+        self.record_count
+    }
+
+    /// Returns the number of host CPUs, that is the highest CPU number plus one.
+    #[inline]
+    pub(crate) fn cpu_count(&self) -> u32 {
+        self.cpu_count
+    }
+
+    /// Returns the distinct event codes of the records.
+    #[inline]
+    pub(crate) fn codes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.codes.iter().copied()
+    }
+
+    /// Tells whether some record has the given event code, which spares
+    /// decoding the whole trace to look for a missing event.
+    #[inline]
+    pub(crate) fn contains_code(&self, code: u32) -> bool {
+        self.codes.contains(&code)
+    }
+
+    /// Returns the lowest TSC of the records, that is the start of the trace.
+    #[inline]
+    pub(crate) fn first_tsc(&self) -> Option<u64> {
+        self.first_tsc
+    }
+
+    /// Returns the TSC of the first record of a CPU, in file order.
+    #[inline]
+    pub(crate) fn cpu_first_tsc(&self, cpu: u32) -> Option<u64> {
+        self.cpu_first_tsc.get(&cpu).copied()
+    }
+
+    /// Returns the number of records out of TSC order, keyed by CPU.
+    #[inline]
+    pub(crate) fn tsc_regressions(&self) -> &BTreeMap<u32, usize> {
+        &self.tsc_regressions
+    }
+
+    /// Decodes the record starting at the given byte offset.
+    pub(crate) fn get(&self, offset: u64) -> Option<Record> {
+        let index = self
+            .buffers
+            .partition_point(|buffer| buffer.start <= offset)
+            .checked_sub(1)?;
+
+        let position = self
+            .buffer_positions(self.buffers[index])
+            .find(|position| position.offset >= offset)
+            .filter(|position| position.offset == offset)?;
+
+        self.decode(position)
+    }
+
+    /// Decodes the event of a record, whose context is already known.
+    fn decode(&self, position: Position) -> Option<Record> {
+        let start = position.offset as usize;
+        let header = read_header(&self.data, start)?;
+
+        let mut extra = [None; EVENT_EXTRA_CAPACITY];
+        let extra_start = start + header.size - header.extra_len * EXTRA_SIZE;
+        for (index, value) in extra.iter_mut().take(header.extra_len).enumerate() {
+            *value = read_u32(&self.data, extra_start + index * EXTRA_SIZE);
+        }
+
+        Some(Record {
+            cpu: position.cpu,
+            domain: position.domain,
+            event: Event {
+                code: EventCode::from(header.code),
+                tsc: position.tsc,
+                extra,
+            },
+        })
+    }
+
+    /// Walks the records of a buffer, tracking their context from its start.
+    fn buffer_positions(&self, buffer: Buffer) -> impl Iterator<Item = Position> + '_ {
+        let mut domain = buffer.domain;
+        let mut tsc = buffer.tsc;
+        let mut offset = buffer.start as usize;
+
+        std::iter::from_fn(move || {
+            let header =
+                read_header(&self.data, offset).filter(|_| offset < buffer.end as usize)?;
+            let start = offset as u64;
+            offset += header.size;
+
+            if header.code == (header.code & TRC_SCHED_TO_RUN) {
+                let extra_0 = match header.extra_len {
+                    0 => None,
+                    _ => read_u32(&self.data, offset - header.extra_len * EXTRA_SIZE),
+                };
+                domain = Domain::from(extra_0.unwrap_or(0));
+            }
+
+            tsc = header.tsc.unwrap_or(tsc);
+
+            Some(Position {
+                offset: start,
+                tsc,
+                cpu: buffer.cpu,
+                domain,
+            })
+        })
+    }
+
+    /// Iterates over the records, in file order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Record> + '_ {
+        self.iter_offsets().map(|(_, record)| record)
+    }
+
+    /// Iterates over the records and their byte offsets, in file order.
+    pub(crate) fn iter_offsets(&self) -> impl Iterator<Item = (u64, Record)> + '_ {
+        self.buffers
+            .iter()
+            .flat_map(|buffer| self.buffer_positions(*buffer))
+            .filter_map(|position| Some((position.offset, self.decode(position)?)))
+    }
+
+    /// Iterates over the records and their byte offsets, in TSC order.
+    ///
+    /// The records are merged as they are walked, only those of the CPUs
+    /// going back in time are held in memory while sorting.
+    #[cfg(test)]
+    pub(crate) fn iter_sorted(&self) -> impl Iterator<Item = (u64, Record)> + '_ {
+        self.iter_sorted_by_key(|_, tsc| tsc)
+    }
+
+    /// Iterates over the records and their byte offsets, in the order of a key
+    /// computed from the CPU and the TSC, which must grow with the TSC of each CPU.
+    pub(crate) fn iter_sorted_by_key<'a, K: Ord + 'a>(
+        &'a self,
+        key: impl Fn(u32, u64) -> K + 'a,
+    ) -> impl Iterator<Item = (u64, Record)> + 'a {
+        self.sorted_positions(key)
+            .filter_map(|position| Some((position.offset, self.decode(position)?)))
+    }
+
+    /// Walks the records of a CPU, in file order.
+    fn cpu_positions(&self, cpu: u32) -> impl Iterator<Item = Position> + '_ {
+        self.buffers
+            .iter()
+            .filter(move |buffer| buffer.cpu == cpu)
+            .flat_map(move |buffer| self.buffer_positions(*buffer))
+    }
+
+    /// Merges the records of each CPU by TSC.
+    ///
+    /// The records of a CPU are sorted already, unless some went back in time:
+    /// the positions of the CPUs with such records are collected and sorted.
+    fn sorted_positions<'a, K: Ord + 'a>(
+        &'a self,
+        key: impl Fn(u32, u64) -> K + 'a,
+    ) -> impl Iterator<Item = Position> + 'a {
+        let (unsorted, sorted) = self
+            .buffers
+            .iter()
+            .map(|buffer| buffer.cpu)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .partition::<Vec<_>, _>(|cpu| self.tsc_regressions.contains_key(cpu));
+
+        let sorted_runs = unsorted.into_iter().map(|cpu| {
+            let mut run = self.cpu_positions(cpu).collect::<Vec<_>>();
+            run.sort_by_key(|position| (position.tsc, position.offset));
+            Box::new(run.into_iter()) as Box<dyn Iterator<Item = _>>
+        });
+
+        let runs = sorted
+            .into_iter()
+            .map(|cpu| Box::new(self.cpu_positions(cpu)) as Box<dyn Iterator<Item = _>>)
+            .chain(sorted_runs)
+            .collect();
+
+        merge_positions(runs, key)
+    }
+}
+
+/// Merges runs of positions sorted by TSC (and offset) into a single one,
+/// ordered by the key of their heads (and offset).
+fn merge_positions<'a, K: Ord + 'a>(
+    mut runs: Vec<Box<dyn Iterator<Item = Position> + 'a>>,
+    key: impl Fn(u32, u64) -> K + 'a,
+) -> impl Iterator<Item = Position> + 'a {
+    let mut heads = runs.iter_mut().map(Iterator::next).collect::<Vec<_>>();
+    let mut heap = heads
+        .iter()
+        .enumerate()
+        .filter_map(|(run, head)| {
+            head.as_ref()
+                .map(|head| Reverse((key(head.cpu, head.tsc), head.offset, run)))
+        })
+        .collect::<BinaryHeap<_>>();
+
+    std::iter::from_fn(move || {
+        let Reverse((_, _, run)) = heap.pop()?;
+        let position = std::mem::replace(&mut heads[run], runs[run].next());
+
+        if let Some(head) = &heads[run] {
+            heap.push(Reverse((key(head.cpu, head.tsc), head.offset, run)));
+        }
+
+        position
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads the header of the record at the given offset,
+/// if the whole record fits in the data.
+fn read_header(data: &[u8], offset: usize) -> Option<Header> {
+    let header = read_u32(data, offset)?;
+    let has_tsc = header & (1 << 31) > 0;
+    let extra_len = ((header >> 28) as usize) & EVENT_EXTRA_CAPACITY;
+
+    let tsc = match has_tsc {
+        true => Some(read_u64(data, offset + HEADER_SIZE)?),
+        false => None,
+    };
+
+    let size = HEADER_SIZE + if has_tsc { TSC_SIZE } else { 0 } + extra_len * EXTRA_SIZE;
+    if offset + size > data.len() {
+        return None;
+    }
+
+    Some(Header {
+        code: header & 0x0FFFFFFF,
+        tsc,
+        extra_len,
+        size,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use memmap2::{Mmap, MmapMut};
+    use xentrace_parser::record::DomainKind;
+
+    use super::TraceStore;
+    use crate::util::test::trace_bytes;
+
+    pub(super) fn map_bytes(bytes: &[u8]) -> Mmap {
+        // Anonymous maps cannot be empty, but a single byte is never read
+        let mut data = MmapMut::map_anon(bytes.len().max(1)).unwrap();
+        data[..bytes.len()].copy_from_slice(bytes);
+        data.make_read_only().unwrap()
+    }
+
+    #[test]
+    fn decode_context() {
+        let mut bytes = trace_bytes(&[
+            (0x0001F003, 5, &[1]),            // gen:cpu_change to CPU 1
+            (0x00021002, 40, &[0x0003_0001]), // d3/v1 scheduled
+            (0x00081002, 50, &[]),
+            (0x0001F003, 6, &[0]), // gen:cpu_change to CPU 0
+            (0x00082002, 10, &[0xAB]),
+        ]);
+        // A record without TSC, followed by a truncated one
+        bytes.extend_from_slice(&((1 << 28) | 0x00082003u32).to_ne_bytes());
+        bytes.extend_from_slice(&0xCDu32.to_ne_bytes());
+        bytes.extend_from_slice(&((1u32 << 31) | 0x00082004).to_ne_bytes());
+
+        let trace = TraceStore::from_bytes(&bytes);
+        assert_eq!(trace.record_count(), 4);
+        assert_eq!(trace.cpu_count(), 2);
+
+        let records = trace.iter().collect::<Vec<_>>();
+        assert_eq!(records[1].cpu(), 1);
+        assert_eq!(records[1].domain().kind(), DomainKind::Guest(3));
+        assert_eq!(records[1].domain().virtual_cpu(), 1);
+        assert_eq!(records[2].cpu(), 0);
+        assert_eq!(records[2].domain().kind(), DomainKind::Default);
+        assert_eq!(records[3].event().tsc(), 10);
+        assert_eq!(records[3].event().extra()[0], Some(0xCD));
+
+        let sorted = trace
+            .iter_sorted()
+            .map(|(offset, record)| (offset, record.event().tsc()))
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, [(60, 10), (76, 10), (16, 40), (32, 50)]);
+        assert_eq!(trace.get(76).map(|record| record.cpu()), Some(0));
+        assert!(trace.get(84).is_none()); // Truncated
+    }
+
+    #[test]
+    fn lazy_merge() {
+        let trace = TraceStore::from_bytes(&trace_bytes(&[
+            (0x0001F003, 0, &[0]),
+            (0x00021002, 10, &[0x0001_0000]), // d1/v0 scheduled on CPU 0
+            (0x00081002, 30, &[]),
+            (0x0001F003, 0, &[1]),
+            (0x00021002, 20, &[0x0002_0000]), // d2/v0 scheduled on CPU 1
+            (0x00081002, 40, &[]),
+            (0x0001F003, 0, &[0]),
+            (0x00081002, 35, &[]),
+            (0x00081002, 50, &[]),
+            (0x0001F003, 0, &[1]),
+            (0x00081002, 45, &[]),
+            (0x0001F003, 0, &[2]),
+            (0x00081002, 42, &[]),
+            (0x00081002, 25, &[]), // Back in time
+        ]));
+
+        let sorted = trace
+            .sorted_positions(|_, tsc| tsc)
+            .map(|position| (position.tsc, position.cpu, u32::from(position.domain)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sorted,
+            [
+                (10, 0, 0x0001_0000),
+                (20, 1, 0x0002_0000),
+                (25, 2, 0x8000_0000),
+                (30, 0, 0x0001_0000),
+                (35, 0, 0x0001_0000), // The domain is carried across the buffers of a CPU
+                (40, 1, 0x0002_0000),
+                (42, 2, 0x8000_0000),
+                (45, 1, 0x0002_0000),
+                (50, 0, 0x0001_0000),
+            ]
+        );
+    }
+
+    #[test]
+    fn checkpoints() {
+        let mut bytes = trace_bytes(&[
+            (0x0001F003, 5, &[0]),
+            (0x00021002, 10, &[0x0001_0000]), // d1/v0 scheduled
+            (0x00081002, 20, &[]),
+            (0x00081002, 30, &[]),
+        ]);
+        // A record without TSC right after a checkpoint
+        bytes.extend_from_slice(&((1 << 28) | 0x00082003u32).to_ne_bytes());
+        bytes.extend_from_slice(&0xCDu32.to_ne_bytes());
+
+        let trace = TraceStore::new(map_bytes(&bytes), 3);
+        assert_eq!(trace.buffers.len(), 2);
+
+        let records = TraceStore::from_bytes(&bytes)
+            .iter_offsets()
+            .map(|(offset, record)| (offset, record.event().tsc(), u32::from(*record.domain())))
+            .collect::<Vec<_>>();
+        assert_eq!(records[3], (56, 30, 0x0001_0000));
+
+        for (offset, tsc, domain) in records {
+            let record = trace.get(offset).unwrap();
+            assert_eq!(record.event().tsc(), tsc);
+            assert_eq!(u32::from(*record.domain()), domain);
+        }
+        assert!(trace.get(20).is_none()); // Not a record boundary
+    }
+}
@@ -1,23 +1,129 @@
+// Allocation macros
+mod alloc {
+    /// Allocates an array of `len` values with `malloc`, returning a null
+    /// pointer on failure. KernelShark releases the memory returned by the
+    /// callbacks with `free`, so it must not come from the Rust allocator.
+    #[macro_export]
+    macro_rules! malloc_array {
+        ($type:ty, $len:expr) => {{
+            let size = ($len) * std::mem::size_of::<$type>();
+            unsafe { libc::malloc(size) as *mut $type }
+        }};
+    }
+}
+
 // CString macros
 mod cstring {
     #[macro_export]
     macro_rules! str_from_ptr {
-        ($ptr:expr) => {
-            if !$ptr.is_null() {
-                let cstr = unsafe { std::ffi::CStr::from_ptr($ptr) };
+        ($ptr:expr) => {{
+            let ptr: *const libc::c_char = $ptr;
+            if !ptr.is_null() {
+                let cstr = unsafe { std::ffi::CStr::from_ptr(ptr) };
                 cstr.to_str().ok()
             } else {
                 None
             }
-        };
+        }};
     }
 
+    /// Copies a string into a `malloc_array!` buffer,
+    /// unless it contains a NUL byte.
     #[macro_export]
     macro_rules! str_into_raw {
-        ($str:expr) => {
-            std::ffi::CString::new($str)
-                .map(std::ffi::CString::into_raw)
-                .ok()
+        ($str:expr) => {{
+            let string = $str;
+            let string: &str = std::convert::AsRef::<str>::as_ref(&string);
+            let len = string.len();
+
+            let ptr = match string.as_bytes().contains(&0) {
+                true => std::ptr::null_mut(),
+                false => $crate::malloc_array!(libc::c_char, len + 1),
+            };
+
+            (!ptr.is_null()).then(|| unsafe {
+                std::ptr::copy_nonoverlapping(string.as_ptr() as *const libc::c_char, ptr, len);
+                ptr.add(len).write(0);
+                ptr
+            })
+        }};
+    }
+}
+
+// Logging macros
+mod log {
+    #[macro_export]
+    macro_rules! log {
+        ($($arg:tt)*) => {
+            eprintln!("[xentrace] {}", format_args!($($arg)*))
         };
     }
 }
+
+// Synthetic XenTrace records
+#[cfg(test)]
+pub(crate) mod test {
+    use std::alloc::{GlobalAlloc, Layout, System};
+
+    use crate::store::{Record, TraceStore};
+
+    /// Rust allocator whose blocks never start where the `malloc` ones do,
+    /// and are preceded by an invalid (zero) `malloc` chunk size.
+    /// Releasing them with `free`, as KernelShark does with the memory
+    /// returned by the callbacks, aborts the tests.
+    struct ForeignAllocator;
+
+    const FOREIGN_OFFSET: usize = 16;
+
+    impl ForeignAllocator {
+        fn layout(layout: Layout) -> (Layout, usize) {
+            let offset = layout.align().max(FOREIGN_OFFSET);
+            let layout = Layout::from_size_align(layout.size() + offset, offset).unwrap();
+            (layout, offset)
+        }
+    }
+
+    unsafe impl GlobalAlloc for ForeignAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let (layout, offset) = Self::layout(layout);
+            let ptr = System.alloc(layout);
+            if ptr.is_null() {
+                return ptr;
+            }
+
+            ptr.write_bytes(0, offset);
+            ptr.add(offset)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let (layout, offset) = Self::layout(layout);
+            System.dealloc(ptr.sub(offset), layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: ForeignAllocator = ForeignAllocator;
+
+    /// Encodes `(code, tsc, extra)` tuples as a raw XenTrace stream.
+    pub(crate) fn trace_bytes(records: &[(u32, u64, &[u32])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        for (code, tsc, extra) in records {
+            let header = (1 << 31) | ((extra.len() as u32) << 28) | code;
+            bytes.extend_from_slice(&header.to_ne_bytes());
+            bytes.extend_from_slice(&tsc.to_ne_bytes());
+            extra
+                .iter()
+                .for_each(|val| bytes.extend_from_slice(&val.to_ne_bytes()));
+        }
+
+        bytes
+    }
+
+    /// Decodes `(code, tsc, extra)` tuples into records, in file order.
+    pub(crate) fn trace_records(records: &[(u32, u64, &[u32])]) -> Vec<Record> {
+        TraceStore::from_bytes(&trace_bytes(records))
+            .iter()
+            .collect()
+    }
+}